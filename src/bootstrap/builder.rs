@@ -384,9 +384,13 @@ impl<'a> Builder<'a> {
                 test::RunPassValgrind,
                 test::MirOpt,
                 test::Codegen,
+                test::Assembly,
                 test::CodegenUnits,
                 test::Incremental,
                 test::Debuginfo,
+                test::DocExamples,
+                test::CoverageMap,
+                test::CoverageRun,
                 test::UiFullDeps,
                 test::RunPassFullDeps,
                 test::RunFailFullDeps,
@@ -793,6 +793,12 @@ default_test!(Codegen {
     suite: "codegen"
 });
 
+default_test!(Assembly {
+    path: "src/test/assembly",
+    mode: "assembly",
+    suite: "assembly"
+});
+
 default_test!(CodegenUnits {
     path: "src/test/codegen-units",
     mode: "codegen-units",
@@ -805,6 +811,24 @@ default_test!(Incremental {
     suite: "incremental"
 });
 
+default_test!(DocExamples {
+    path: "src/test/doc-examples",
+    mode: "doc-examples",
+    suite: "doc-examples"
+});
+
+default_test!(CoverageMap {
+    path: "src/test/coverage-map",
+    mode: "coverage-map",
+    suite: "coverage-map"
+});
+
+default_test!(CoverageRun {
+    path: "src/test/coverage-run",
+    mode: "coverage-run",
+    suite: "coverage-run"
+});
+
 default_test!(Debuginfo {
     path: "src/test/debuginfo",
     // What this runs varies depending on the native platform being apple
@@ -935,7 +959,7 @@ impl Step for Compiletest {
         let suite_path = self.path.unwrap_or("");
 
         // Skip codegen tests if they aren't enabled in configuration.
-        if !builder.config.codegen_tests && suite == "codegen" {
+        if !builder.config.codegen_tests && (suite == "codegen" || suite == "assembly") {
             return;
         }
 
@@ -1017,6 +1041,10 @@ impl Step for Compiletest {
             cmd.arg("--bless");
         }
 
+        if builder.config.rust_debug_assertions {
+            cmd.arg("--rustc-debug-assertions");
+        }
+
         let compare_mode = builder.config.cmd.compare_mode().or(self.compare_mode);
 
         if let Some(ref nodejs) = builder.config.nodejs {
@@ -1097,7 +1125,7 @@ impl Step for Compiletest {
 
         cmd.args(&test_args);
 
-        if builder.is_verbose() {
+        for _ in 0..builder.verbosity {
             cmd.arg("--verbose");
         }
 
@@ -1113,6 +1141,16 @@ impl Step for Compiletest {
             if !builder.config.dry_run {
                 let llvm_version = output(Command::new(&llvm_config).arg("--version"));
                 cmd.arg("--llvm-version").arg(llvm_version);
+                // Any test may declare `needs-llvm-components`, so every suite
+                // gets to know which ones were built.
+                let llvm_components = output(Command::new(&llvm_config).arg("--components"));
+                cmd.arg("--llvm-components").arg(llvm_components.trim());
+                // The tools built along with LLVM, like `llvm-profdata` for
+                // the coverage suites.
+                let llvm_bin_dir = output(Command::new(&llvm_config).arg("--bindir"));
+                cmd.arg("--llvm-bin-dir").arg(llvm_bin_dir.trim());
+            } else {
+                cmd.arg("--llvm-components").arg("");
             }
             if !builder.is_rust_llvm(target) {
                 cmd.arg("--system-llvm");
@@ -1121,7 +1159,6 @@ impl Step for Compiletest {
             // Only pass correct values for these flags for the `run-make` suite as it
             // requires that a C++ compiler was configured which isn't always the case.
             if !builder.config.dry_run && suite == "run-make-fulldeps" {
-                let llvm_components = output(Command::new(&llvm_config).arg("--components"));
                 let llvm_cxxflags = output(Command::new(&llvm_config).arg("--cxxflags"));
                 cmd.arg("--cc")
                     .arg(builder.cc(target))
@@ -1129,8 +1166,6 @@ impl Step for Compiletest {
                     .arg(builder.cxx(target).unwrap())
                     .arg("--cflags")
                     .arg(builder.cflags(target).join(" "))
-                    .arg("--llvm-components")
-                    .arg(llvm_components.trim())
                     .arg("--llvm-cxxflags")
                     .arg(llvm_cxxflags.trim());
                 if let Some(ar) = builder.ar(target) {
@@ -1145,14 +1180,24 @@ impl Step for Compiletest {
             return;
         }
 
+        if !builder.config.llvm_enabled {
+            cmd.arg("--llvm-components").arg("");
+        }
         if suite != "run-make-fulldeps" {
-            cmd.arg("--cc")
-                .arg("")
-                .arg("--cxx")
-                .arg("")
-                .arg("--cflags")
-                .arg("")
-                .arg("--llvm-components")
+            // Tests may still build C code of their own with `aux-native-lib`,
+            // for which a C compiler is enough.
+            if !builder.config.dry_run {
+                cmd.arg("--cc")
+                    .arg(builder.cc(target))
+                    .arg("--cflags")
+                    .arg(builder.cflags(target).join(" "));
+                if let Some(ar) = builder.ar(target) {
+                    cmd.arg("--ar").arg(ar);
+                }
+            } else {
+                cmd.arg("--cc").arg("").arg("--cflags").arg("");
+            }
+            cmd.arg("--cxx")
                 .arg("")
                 .arg("--llvm-cxxflags")
                 .arg("");
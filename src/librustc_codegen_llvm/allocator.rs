@@ -70,6 +70,10 @@ pub(crate) unsafe fn codegen(tcx: TyCtxt, mods: &ModuleLlvm, kind: AllocatorKind
        if tcx.sess.target.target.options.requires_uwtable {
            attributes::emit_uwtable(llfn, true);
        }
+        // The shim passes arguments and return values between instrumented
+        // functions, so MemorySanitizer has to see it to keep track of which
+        // of them are initialized.
+        attributes::sanitize(tcx.sess, llfn);
 
         let callee = CString::new(kind.fn_name(method.name)).unwrap();
         let callee = llvm::LLVMRustGetOrInsertFunction(llmod,
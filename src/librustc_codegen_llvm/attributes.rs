@@ -65,6 +65,16 @@ pub fn set_optimize_for_size(val: &'ll Value, optimize: bool) {
     Attribute::OptimizeForSize.toggle_llfn(Function, val, optimize);
 }
 
+/// Tell LLVM to instrument the function for the sanitizer in use, if any.
+pub fn sanitize(sess: &Session, llfn: &'ll Value) {
+    match sess.opts.debugging_opts.sanitizer {
+        Some(Sanitizer::Address) => Attribute::SanitizeAddress.apply_llfn(Function, llfn),
+        Some(Sanitizer::Memory) => Attribute::SanitizeMemory.apply_llfn(Function, llfn),
+        Some(Sanitizer::Thread) => Attribute::SanitizeThread.apply_llfn(Function, llfn),
+        Some(Sanitizer::Leak) | None => {}
+    }
+}
+
 /// Tell LLVM if this function should be 'naked', i.e. skip the epilogue and prologue.
 #[inline]
 pub fn naked(val: &'ll Value, is_naked: bool) {
@@ -94,8 +104,8 @@ pub fn set_probestack(cx: &CodegenCx<'ll, '_>, llfn: &'ll Value) {
         _ => {}
     }
 
-    // probestack doesn't play nice either with pgo-gen.
-    if cx.sess().opts.debugging_opts.pgo_gen.is_some() {
+    // probestack doesn't play nice either with `-C profile-generate`.
+    if cx.sess().opts.cg.profile_generate.enabled() {
         return;
     }
 
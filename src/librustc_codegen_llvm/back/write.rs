@@ -21,7 +21,7 @@ use rustc_incremental::{copy_cgu_workproducts_to_incr_comp_cache_dir, in_incr_co
 use rustc::dep_graph::{WorkProduct, WorkProductId, WorkProductFileKind};
 use rustc::middle::cstore::{LinkMeta, EncodedMetadata};
 use rustc::session::config::{self, OutputFilenames, OutputType, Passes, SomePasses,
-                             AllPasses, Sanitizer, Lto};
+                             AllPasses, Sanitizer, Lto, SwitchWithOptPath};
 use rustc::session::Session;
 use rustc::util::nodemap::FxHashMap;
 use time_graph::{self, TimeGraph, Timeline};
@@ -218,8 +218,8 @@ pub struct ModuleConfig {
     /// Some(level) to optimize binary size, or None to not affect program size.
     opt_size: Option<llvm::CodeGenOptSize>,
 
-    pgo_gen: Option<String>,
-    pgo_use: String,
+    pgo_gen: SwitchWithOptPath,
+    pgo_use: Option<PathBuf>,
 
     // Flags indicating which outputs to produce.
     emit_no_opt_bc: bool,
@@ -255,8 +255,8 @@ impl ModuleConfig {
             opt_level: None,
             opt_size: None,
 
-            pgo_gen: None,
-            pgo_use: String::new(),
+            pgo_gen: SwitchWithOptPath::Disabled,
+            pgo_use: None,
 
             emit_no_opt_bc: false,
             emit_bc: false,
@@ -288,7 +288,7 @@ impl ModuleConfig {
         self.time_passes = sess.time_passes();
         self.inline_threshold = sess.opts.cg.inline_threshold;
         self.obj_is_bitcode = sess.target.target.options.obj_is_bitcode ||
-                              sess.opts.debugging_opts.cross_lang_lto.enabled();
+                              sess.opts.cg.linker_plugin_lto.enabled();
         let embed_bitcode = sess.target.target.options.embed_bitcode ||
                             sess.opts.debugging_opts.embed_bitcode;
         if embed_bitcode {
@@ -908,18 +908,16 @@ pub fn start_async_codegen(tcx: TyCtxt,
     let mut allocator_config = ModuleConfig::new(vec![]);
 
     if let Some(ref sanitizer) = sess.opts.debugging_opts.sanitizer {
-        match *sanitizer {
-            Sanitizer::Address => {
-                modules_config.passes.push("asan".to_owned());
-                modules_config.passes.push("asan-module".to_owned());
-            }
-            Sanitizer::Memory => {
-                modules_config.passes.push("msan".to_owned())
-            }
-            Sanitizer::Thread => {
-                modules_config.passes.push("tsan".to_owned())
-            }
-            _ => {}
+        let passes: &[&str] = match *sanitizer {
+            Sanitizer::Address => &["asan", "asan-module"],
+            Sanitizer::Memory => &["msan"],
+            Sanitizer::Thread => &["tsan"],
+            Sanitizer::Leak => &[],
+        };
+        // The allocator shim is instrumented like the rest of the crate.
+        for pass in passes {
+            modules_config.passes.push(pass.to_string());
+            allocator_config.passes.push(pass.to_string());
         }
     }
 
@@ -927,8 +925,8 @@ pub fn start_async_codegen(tcx: TyCtxt,
         modules_config.passes.push("insert-gcov-profiling".to_owned())
     }
 
-    modules_config.pgo_gen = sess.opts.debugging_opts.pgo_gen.clone();
-    modules_config.pgo_use = sess.opts.debugging_opts.pgo_use.clone();
+    modules_config.pgo_gen = sess.opts.cg.profile_generate.clone();
+    modules_config.pgo_use = sess.opts.cg.profile_use.clone();
 
     modules_config.opt_level = Some(get_llvm_opt_level(sess.opts.optimize));
     modules_config.opt_size = Some(get_llvm_opt_size(sess.opts.optimize));
@@ -1360,10 +1358,10 @@ fn execute_work_item(cgcx: &CodegenContext,
             // settings.
             let needs_lto = needs_lto && module.kind != ModuleKind::Metadata;
 
-            // Don't run LTO passes when cross-lang LTO is enabled. The linker
-            // will do that for us in this case.
+            // Don't run LTO passes when linker-plugin LTO is enabled. The
+            // linker will do that for us in this case.
             let needs_lto = needs_lto &&
-                !cgcx.opts.debugging_opts.cross_lang_lto.enabled();
+                !cgcx.opts.cg.linker_plugin_lto.enabled();
 
             if needs_lto {
                 Ok(WorkItemResult::NeedsLTO(module))
@@ -2064,17 +2062,22 @@ pub unsafe fn with_llvm_pmb(llmod: &llvm::Module,
     let opt_size = config.opt_size.unwrap_or(llvm::CodeGenOptSizeNone);
     let inline_threshold = config.inline_threshold;
 
-    let pgo_gen_path = config.pgo_gen.as_ref().map(|s| {
-        let s = if s.is_empty() { "default_%m.profraw" } else { s };
-        CString::new(s.as_bytes()).unwrap()
-    });
-
-    let pgo_use_path = if config.pgo_use.is_empty() {
-        None
-    } else {
-        Some(CString::new(config.pgo_use.as_bytes()).unwrap())
+    // The profiling runtime replaces `%m` with a signature of the binary, so
+    // that the profiles of different programs written to the same directory
+    // don't clobber each other.
+    let pgo_gen_path = match config.pgo_gen {
+        SwitchWithOptPath::Enabled(ref dir) => {
+            let path = match *dir {
+                Some(ref dir) => dir.join("default_%m.profraw"),
+                None => PathBuf::from("default_%m.profraw"),
+            };
+            Some(path2cstr(&path))
+        }
+        SwitchWithOptPath::Disabled => None,
     };
 
+    let pgo_use_path = config.pgo_use.as_ref().map(|path| path2cstr(path));
+
     llvm::LLVMRustConfigurePassManagerBuilder(
         builder,
         opt_level,
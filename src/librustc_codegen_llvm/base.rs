@@ -730,8 +730,8 @@ pub fn codegen_crate<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx, 'tcx>,
         }
     }
 
-    if (tcx.sess.opts.debugging_opts.pgo_gen.is_some() ||
-        !tcx.sess.opts.debugging_opts.pgo_use.is_empty()) &&
+    if (tcx.sess.opts.cg.profile_generate.enabled() ||
+        tcx.sess.opts.cg.profile_use.is_some()) &&
         unsafe { !llvm::LLVMRustPGOAvailable() }
     {
         tcx.sess.fatal("this compiler's LLVM does not support PGO");
@@ -12,7 +12,7 @@ use syntax_pos::symbol::Symbol;
 use back::write::create_target_machine;
 use llvm;
 use rustc::session::Session;
-use rustc::session::config::PrintRequest;
+use rustc::session::config::{PrintRequest, Sanitizer};
 use libc::c_int;
 use std::ffi::CString;
 use syntax::feature_gate::UnstableFeatures;
@@ -65,6 +65,12 @@ unsafe fn configure_llvm(sess: &Session) {
         if sess.opts.debugging_opts.disable_instrumentation_preinliner {
             add("-disable-preinline");
         }
+        if let Some(Sanitizer::Memory) = sess.opts.debugging_opts.sanitizer {
+            let track_origins = sess.opts.debugging_opts.sanitizer_memory_track_origins;
+            if track_origins != 0 {
+                add(&format!("-msan-track-origins={}", track_origins));
+            }
+        }
 
         for arg in &sess.opts.cg.llvm_args {
             add(&(*arg));
@@ -0,0 +1,25 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checks that an increment of an argument is lowered to a single `lea`.
+
+// only-x86_64
+// assembly-output: emit-asm
+// compile-flags: -O
+
+#![crate_type = "lib"]
+
+// CHECK-LABEL: add_one:
+// CHECK: lea{{[lq]?}} 1(
+// CHECK-NEXT: ret
+#[no_mangle]
+pub fn add_one(x: u32) -> u32 {
+    x + 1
+}
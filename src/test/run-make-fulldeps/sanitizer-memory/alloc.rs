@@ -0,0 +1,26 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Allocates, grows and frees through the allocator shim, and branches on
+// what was stored, which MemorySanitizer reports if it lost track of the
+// memory being initialized.
+
+fn main() {
+    let b = Box::new(7u64);
+    let mut v = vec![*b; 4];
+    for i in 0..64 {
+        v.push(i);
+    }
+    let b2 = v.into_boxed_slice();
+    let total: u64 = b2.iter().sum();
+    if total != 4 * 7 + 63 * 64 / 2 {
+        std::process::exit(1);
+    }
+}
@@ -18,6 +18,14 @@ endif
 HTMLDOCCK := $(PYTHON) $(S)/src/etc/htmldocck.py
 CGREP := "$(S)/src/etc/cat-and-grep.sh"
 
+# The tools of the LLVM rustc was built with, when compiletest knows where they
+# are, and the ones in PATH otherwise.
+ifdef LLVM_BIN_DIR
+LLVM_PROFDATA := "$(LLVM_BIN_DIR)/llvm-profdata"
+else
+LLVM_PROFDATA := llvm-profdata
+endif
+
 # This is the name of the binary we will generate and run; use this
 # e.g. for `$(CC) -o $(RUN_BINFILE)`.
 RUN_BINFILE = $(TMPDIR)/$(1)
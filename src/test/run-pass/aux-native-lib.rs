@@ -0,0 +1,23 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checks that a test can be linked against a C library built by compiletest.
+
+// aux-native-lib: auxiliary/native-add-one.c
+// ignore-emscripten no C compiler for the target
+// ignore-wasm32-bare no C compiler for the target
+
+extern "C" {
+    fn native_add_one(x: i32) -> i32;
+}
+
+fn main() {
+    assert_eq!(unsafe { native_add_one(41) }, 42);
+}
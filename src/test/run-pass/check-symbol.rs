@@ -0,0 +1,31 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checks that `check-symbol` finds the functions a binary defines, and that
+// `check-no-symbol` doesn't find those that were never built into it.
+
+// ignore-emscripten no symbol table to list
+// ignore-wasm32-bare no symbol table to list
+// check-symbol: check_symbol_called
+// check-no-symbol: check_symbol_unused
+
+#[inline(never)]
+fn check_symbol_called() -> u32 {
+    42
+}
+
+#[allow(dead_code)]
+fn check_symbol_unused() -> u32 {
+    0
+}
+
+fn main() {
+    assert_eq!(check_symbol_called(), 42);
+}
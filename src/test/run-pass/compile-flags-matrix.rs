@@ -0,0 +1,20 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Checks that every revision of a `compile-flags-matrix` is built with its own
+// combination of flags.
+
+// compile-flags-matrix: [-O | -C opt-level=0] x [--cfg a | --cfg b]
+
+fn main() {
+    assert!(cfg!(a) != cfg!(b));
+    assert_eq!(cfg!(a), cfg!(O__cfg_a) || cfg!(C_opt_level_0__cfg_a));
+    assert_eq!(cfg!(b), cfg!(O__cfg_b) || cfg!(C_opt_level_0__cfg_b));
+}
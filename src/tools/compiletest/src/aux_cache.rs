@@ -0,0 +1,137 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sharing the auxiliary crates tests build.
+//!
+//! Lots of tests `aux-build` the same helper crates the same way. With
+//! `--share-aux-builds`, the files an auxiliary build produces are kept in
+//! `aux-cache` under the build directory, keyed by a hash of the compiler
+//! command line and environment, the contents of the crate's main file and the
+//! crates built before it for the same test. The next test building the crate
+//! the same way copies them to its own auxiliary directory instead of running
+//! the compiler.
+//!
+//! The cache only lasts for one compiletest run: every process keeps its own,
+//! and removes it when its tests are done. Nothing a build depends on but its
+//! command line and environment, like the compiler, the sysroot or the modules
+//! of the crate, is expected to change within a run, so those aren't hashed.
+//!
+//! Builds of the same crate are serialized. An entry is first put together in
+//! a temporary directory and then renamed into place, so a build that fails
+//! to be stored never leaves half of one.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use command::Command;
+use common::Config;
+
+lazy_static! {
+    static ref LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+fn cache_dir(config: &Config) -> PathBuf {
+    config.build_base.join("aux-cache").join(process::id().to_string())
+}
+
+/// Removes the crates this run kept.
+pub fn clear(config: &Config) {
+    let _ = fs::remove_dir_all(cache_dir(config));
+}
+
+/// The key of the build of `aux_file` with `command` into `aux_dir`, after the
+/// crates with the keys `deps`.
+pub fn key(
+    config: &Config,
+    command: &Command,
+    aux_dir: &Path,
+    aux_file: &Path,
+    deps: &[String],
+) -> String {
+    let mut hash = DefaultHasher::new();
+    config.stage_id.hash(&mut hash);
+    // The auxiliary directory is a different one for every test.
+    command
+        .command_line()
+        .replace(aux_dir.to_str().unwrap(), "{aux}")
+        .hash(&mut hash);
+    command.env_changes().hash(&mut hash);
+    if let Ok(contents) = fs::read(aux_file) {
+        contents.hash(&mut hash);
+    }
+    deps.hash(&mut hash);
+    format!("{:x}", hash.finish())
+}
+
+/// The lock for building the crate with `key` in this process.
+pub fn lock(key: &str) -> Arc<Mutex<()>> {
+    LOCKS
+        .lock()
+        .unwrap()
+        .entry(key.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(())))
+        .clone()
+}
+
+/// Copies the files of the crate with `key` to `aux_dir`, if it was built
+/// before.
+pub fn fetch(config: &Config, key: &str, aux_dir: &Path) -> bool {
+    let entry = cache_dir(config).join(key);
+    let files = match fs::read_dir(&entry) {
+        Ok(files) => files,
+        Err(_) => return false,
+    };
+    for file in files {
+        let file = file.unwrap();
+        fs::copy(file.path(), aux_dir.join(file.file_name())).unwrap();
+    }
+    debug!("reused auxiliary build {}", entry.display());
+    true
+}
+
+/// The files in `aux_dir`, with the time they were last modified.
+pub fn files(aux_dir: &Path) -> HashMap<OsString, SystemTime> {
+    fs::read_dir(aux_dir)
+        .unwrap()
+        .map(|file| file.unwrap())
+        .filter(|file| file.file_type().unwrap().is_file())
+        .map(|file| (file.file_name(), file.metadata().unwrap().modified().unwrap()))
+        .collect()
+}
+
+/// Keeps the files the build of the crate with `key` added to or changed in
+/// `aux_dir`, given the files that were there before.
+pub fn store(
+    config: &Config,
+    key: &str,
+    aux_dir: &Path,
+    before: &HashMap<OsString, SystemTime>,
+) {
+    let entry = cache_dir(config).join(key);
+    if entry.exists() {
+        return;
+    }
+    let tmp = cache_dir(config).join(format!("{}.tmp", key));
+    let _ = fs::remove_dir_all(&tmp);
+    fs::create_dir_all(&tmp).unwrap();
+    for (name, modified) in files(aux_dir) {
+        if before.get(&name) != Some(&modified) {
+            fs::copy(aux_dir.join(&name), tmp.join(&name)).unwrap();
+        }
+    }
+    fs::rename(&tmp, &entry).unwrap();
+}
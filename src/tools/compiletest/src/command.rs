@@ -0,0 +1,208 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A `std::process::Command` that remembers what it was told to run.
+//!
+//! `std`'s `Command` doesn't give out its program, arguments or environment,
+//! but tests print the command lines they run and key caches on them. This one
+//! records them as they are set, and otherwise passes everything on.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{self, Child, ExitStatus, Output, Stdio};
+
+use util::quote_arg;
+
+pub struct Command {
+    inner: process::Command,
+    program: OsString,
+    args: Vec<OsString>,
+    env_cleared: bool,
+    /// The variables set, or removed if `None`, in the order that happened.
+    env: Vec<(OsString, Option<OsString>)>,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Command {
+        Command {
+            inner: process::Command::new(program.as_ref()),
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env_cleared: false,
+            env: Vec::new(),
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command {
+        self.inner.arg(arg.as_ref());
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    pub fn env<K, V>(&mut self, key: K, value: V) -> &mut Command
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.inner.env(key.as_ref(), value.as_ref());
+        self.env.push((key.as_ref().to_os_string(), Some(value.as_ref().to_os_string())));
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Command
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Command {
+        self.inner.env_remove(key.as_ref());
+        self.env.push((key.as_ref().to_os_string(), None));
+        self
+    }
+
+    pub fn env_clear(&mut self) -> &mut Command {
+        self.inner.env_clear();
+        self.env_cleared = true;
+        self.env.clear();
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command {
+        self.inner.current_dir(dir);
+        self
+    }
+
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdin(cfg);
+        self
+    }
+
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdout(cfg);
+        self
+    }
+
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stderr(cfg);
+        self
+    }
+
+    /// Runs `f` in the child process right before it execs the program, as
+    /// `std::os::unix::process::CommandExt::before_exec` does.
+    #[cfg(unix)]
+    pub fn before_exec<F>(&mut self, f: F) -> &mut Command
+    where
+        F: FnMut() -> io::Result<()> + Send + Sync + 'static,
+    {
+        use std::os::unix::process::CommandExt;
+
+        self.inner.before_exec(f);
+        self
+    }
+
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        self.inner.spawn()
+    }
+
+    pub fn output(&mut self) -> io::Result<Output> {
+        self.inner.output()
+    }
+
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        self.inner.status()
+    }
+
+    /// The program, as it was given to `new`.
+    pub fn get_program(&self) -> &OsStr {
+        &self.program
+    }
+
+    /// The variables the environment of the program differs in from ours, or
+    /// from an empty one if it was cleared, with `None` for those removed.
+    pub fn env_changes(&self) -> BTreeMap<&OsStr, Option<&OsStr>> {
+        self.env
+            .iter()
+            .map(|&(ref key, ref value)| (&key[..], value.as_ref().map(|v| &v[..])))
+            .collect()
+    }
+
+    /// The program and arguments, each quoted for the shell of the host, so
+    /// that the result can be pasted into a terminal.
+    pub fn command_line(&self) -> String {
+        Some(&self.program)
+            .into_iter()
+            .chain(&self.args)
+            .map(|s| quote_arg(&s.to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Like `env` would run it: `-i` if the environment was cleared, `-u` for the
+/// variables removed, then the ones set and the command line.
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let env = self.env_changes();
+        if self.env_cleared {
+            write!(f, "env -i ")?;
+        } else if env.values().any(|value| value.is_none()) {
+            write!(f, "env ")?;
+        }
+        for key in env.iter().filter(|&(_, value)| value.is_none()).map(|(key, _)| key) {
+            write!(f, "-u {} ", key.to_string_lossy())?;
+        }
+        for (key, value) in env.iter().filter_map(|(key, value)| value.map(|v| (key, v))) {
+            write!(f, "{}={} ", key.to_string_lossy(), quote_arg(&value.to_string_lossy()))?;
+        }
+        write!(f, "{}", self.command_line())
+    }
+}
+
+#[test]
+fn test_command_line() {
+    let mut command = Command::new("rustc");
+    command.arg("a.rs").args(&["--cfg", "feature=\"x\"", ""]);
+    if cfg!(windows) {
+        assert_eq!(command.command_line(), r#"rustc a.rs --cfg "feature=\"x\"" """#);
+    } else {
+        assert_eq!(command.command_line(), r#"rustc a.rs --cfg 'feature="x"' ''"#);
+    }
+    assert_eq!(command.get_program().to_str(), Some("rustc"));
+}
+
+#[test]
+fn test_debug_env() {
+    let mut command = Command::new("rustc");
+    command.env("RUST_LOG", "info").env_remove("RUSTFLAGS").env("RUST_LOG", "debug");
+    assert_eq!(format!("{:?}", command), "env -u RUSTFLAGS RUST_LOG=debug rustc");
+    command.env_clear().env("PATH", "/bin").arg("-V");
+    assert_eq!(format!("{:?}", command), "env -i PATH=/bin rustc -V");
+}
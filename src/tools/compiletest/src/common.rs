@@ -9,12 +9,13 @@
 // except according to those terms.
 pub use self::Mode::*;
 
+use std::env;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use test::ColorConfig;
-use util::PathBufExt;
+use test::{ColorConfig, OutputFormat};
+use util::{self, PathBufExt};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mode {
@@ -33,6 +34,10 @@ pub enum Mode {
     RunMake,
     Ui,
     MirOpt,
+    Assembly,
+    CoverageMap,
+    CoverageRun,
+    DocExamples,
 }
 
 impl Mode {
@@ -44,6 +49,8 @@ impl Mode {
             Pretty => ".pretty",
             DebugInfoGdb => ".gdb",
             DebugInfoLldb => ".lldb",
+            CoverageMap => ".cov-map",
+            CoverageRun => ".cov-run",
             _ => "",
         }
     }
@@ -68,6 +75,10 @@ impl FromStr for Mode {
             "run-make" => Ok(RunMake),
             "ui" => Ok(Ui),
             "mir-opt" => Ok(MirOpt),
+            "assembly" => Ok(Assembly),
+            "coverage-map" => Ok(CoverageMap),
+            "coverage-run" => Ok(CoverageRun),
+            "doc-examples" => Ok(DocExamples),
             _ => Err(()),
         }
     }
@@ -91,6 +102,10 @@ impl fmt::Display for Mode {
             RunMake => "run-make",
             Ui => "ui",
             MirOpt => "mir-opt",
+            Assembly => "assembly",
+            CoverageMap => "coverage-map",
+            CoverageRun => "coverage-run",
+            DocExamples => "doc-examples",
         };
         fmt::Display::fmt(s, f)
     }
@@ -119,6 +134,26 @@ impl CompareMode {
     }
 }
 
+/// Which tests get to keep their executables, output dumps and scratch
+/// directories once they're done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeepArtifacts {
+    All,
+    Failing,
+    Never,
+}
+
+impl KeepArtifacts {
+    pub fn parse(s: String) -> KeepArtifacts {
+        match s.as_str() {
+            "all" => KeepArtifacts::All,
+            "failing" => KeepArtifacts::Failing,
+            "never" => KeepArtifacts::Never,
+            x => panic!("unknown --keep-artifacts option: {}", x),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     /// Whether to overwrite stderr/stdout files instead of complaining about changes in output
@@ -136,6 +171,10 @@ pub struct Config {
     /// The rustdoc executable
     pub rustdoc_path: Option<PathBuf>,
 
+    /// A released rustc whose pretty-printer output `pretty` tests have to
+    /// match, so that changes to the output between releases are noticed
+    pub reference_rustc: Option<PathBuf>,
+
     /// The python executable to use for LLDB
     pub lldb_python: String,
 
@@ -145,6 +184,15 @@ pub struct Config {
     /// The llvm FileCheck binary path
     pub llvm_filecheck: Option<PathBuf>,
 
+    /// The directory with the LLVM tools built along with the compiler, like
+    /// `llvm-profdata`
+    pub llvm_bin_dir: Option<PathBuf>,
+
+    /// The program listing the symbols of binaries for the `check-symbol`
+    /// header, unless targeting MSVC, where the linker writes them to a map
+    /// file
+    pub nm: String,
+
     /// The valgrind path
     pub valgrind_path: Option<String>,
 
@@ -152,6 +200,26 @@ pub struct Config {
     /// (or, alternatively, to silently run them like regular run-pass tests).
     pub force_valgrind: bool,
 
+    /// The exit code valgrind is told to use when it found errors
+    pub valgrind_error_code: i32,
+
+    /// Build run-pass and run-fail tests with this sanitizer (e.g. `address`),
+    /// and check them with it instead of with valgrind
+    pub sanitizer: Option<String>,
+
+    /// Fail run-pass tests whose compilation emits warnings, unless they have
+    /// the `allow-warnings` header
+    pub deny_warnings: bool,
+
+    /// Check compile-fail tests with `--emit=metadata` instead of building
+    /// them, unless they have the `needs-codegen` header
+    pub compile_fail_metadata: bool,
+
+    /// Also build the compile-fail tests checked with `--emit=metadata`, fail
+    /// those that report more errors when built, and report how long checking
+    /// and building them took
+    pub compare_compile_fail_metadata: bool,
+
     /// The directory containing the tests to run
     pub src_base: PathBuf,
 
@@ -170,16 +238,76 @@ pub struct Config {
     /// Only run tests that match this filter
     pub filter: Option<String>,
 
+    /// Skip tests whose name contains any of these
+    pub skip: Vec<String>,
+
     /// Exactly match the filter, rather than a substring
     pub filter_exact: bool,
 
+    /// Print the tests and what their headers say about them instead of
+    /// running them
+    pub list: bool,
+
+    /// Header lines treated as if every test had them, from `--force-header`
+    pub forced_headers: Vec<String>,
+
+    /// Names of headers to ignore in all tests, from `--suppress-header`
+    pub suppressed_headers: Vec<String>,
+
+    /// Environment variables passed on to the processes tests run, in addition
+    /// to the ones always passed on
+    pub pass_env: Vec<String>,
+
+    /// Exit as soon as a test fails
+    pub fail_fast: bool,
+
+    /// Only run the tests that failed in the previous run, if any did
+    pub resume: bool,
+
+    /// A file listing known-bad tests, which are run, but whose failures are
+    /// reported separately and don't fail the suite
+    pub quarantine: Option<PathBuf>,
+
+    /// Only run the tests falling into shard `.0` (counting from 1) out of `.1`
+    pub shard: Option<(u64, u64)>,
+
     /// Write out a parseable log of tests that were run
     pub logfile: Option<PathBuf>,
 
+    /// Write a JUnit XML report of the suite to this path
+    pub junit_report: Option<PathBuf>,
+
+    /// Print this many of the slowest tests once the suite is done
+    pub slowest: Option<usize>,
+
+    /// Write an HTML report of the suite to `report.html` in `build_base`
+    pub html_report: bool,
+
+    /// Write the compile and run times of every test to this CSV file
+    pub timings_csv: Option<PathBuf>,
+
+    /// Write the size of the binary built by every test to this CSV file
+    pub binary_sizes_csv: Option<PathBuf>,
+
+    /// Merge a JSON summary of the suite, keyed by mode, into this file
+    pub summary_json: Option<PathBuf>,
+
+    /// Record the processes tests run, with their output, to this directory
+    pub record_procs: Option<PathBuf>,
+
+    /// Replay the processes recorded in this directory instead of running them
+    pub replay_procs: Option<PathBuf>,
+
     /// A command line to prefix program execution with,
     /// for running under valgrind
     pub runtool: Option<String>,
 
+    /// A QEMU user-mode emulator (e.g. `qemu-arm`) to run compiled tests with
+    pub qemu: Option<String>,
+
+    /// The target sysroot the QEMU emulator should load libraries from
+    pub qemu_sysroot: Option<PathBuf>,
+
     /// Flags to pass to the compiler when building for the host
     pub host_rustcflags: Option<String>,
 
@@ -204,12 +332,19 @@ pub struct Config {
     /// Version of LLDB
     pub lldb_version: Option<String>,
 
-    /// Version of LLVM
-    pub llvm_version: Option<String>,
+    /// Version of LLVM, encoded as ((major * 1000) + minor) * 1000 + patch
+    pub llvm_version: Option<u32>,
 
     /// Is LLVM a system LLVM
     pub system_llvm: bool,
 
+    /// Whether the compiler under test or the tests it builds have debug
+    /// assertions enabled, for `ignore-debug`
+    pub debug_assertions: bool,
+
+    /// Whether tests are built with optimizations, for `ignore-optimized`
+    pub optimize_tests: bool,
+
     /// Path to the android tools
     pub android_cross_path: PathBuf,
 
@@ -225,11 +360,63 @@ pub struct Config {
     /// the path containing LLDB's Python module
     pub lldb_python_dir: Option<String>,
 
-    /// Explain what's going on
-    pub verbose: bool,
+    /// Print the output of every process as it runs, rather than once it exited
+    pub stream_output: bool,
+
+    /// Explain what's going on: 1 logs the configuration, the commands run and
+    /// the progress of the suite, 2 also shows the output of every process
+    pub verbosity: usize,
+
+    /// Which log messages to print, as a filter like `info` or
+    /// `compiletest::runtest=debug`. Taken from `--log` or `COMPILETEST_LOG`,
+    /// and otherwise derived from `verbosity`.
+    pub log: Option<String>,
+
+    /// How many tests to run concurrently, or `None` to let libtest decide
+    pub jobs: Option<usize>,
+
+    /// Kill the compiler or a test binary once it ran for this many seconds
+    pub timeout: Option<u64>,
+
+    /// How many times to rerun a failed run-pass or run-fail test before
+    /// giving up on it
+    pub retry: usize,
+
+    /// Have the compiler write dep-info for tests and check that it only lists
+    /// files that exist in the test tree
+    pub check_dep_info: bool,
+
+    /// Compile every test this many times instead of testing it, recording
+    /// how long that took
+    pub bench_compile: Option<usize>,
 
-    /// Print one character per test instead of one line
-    pub quiet: bool,
+    /// How many bytes of each of the stdout and stderr of a process to keep;
+    /// the middle of longer output is left out
+    pub output_limit: usize,
+
+    /// Build auxiliary crates once per run and copy them to the tests that
+    /// build them the same way
+    pub share_aux_builds: bool,
+
+    /// Write the complete output of processes to the test's `.out` and `.err`
+    /// files as it arrives, rather than the part of it that was kept
+    pub spill_output: bool,
+
+    /// Let processes dump core and move the dumps of the ones that crash next
+    /// to the output of their test
+    pub collect_core_dumps: bool,
+
+    /// The one test to run with `--debug-test`, printing the commands it runs,
+    /// keeping everything it builds and writing an `env.sh` next to its binary
+    pub debug_test: Option<String>,
+
+    /// Stop the binary of the test run with `--debug-test` before it starts,
+    /// until a debugger is attached
+    pub debug_test_pause: bool,
+
+    /// How to format the console output. `Json` also emits a record per test,
+    /// see `report::run_recorded`.
+    pub format: OutputFormat,
 
     /// Whether to use colors in test.
     pub color: ColorConfig,
@@ -240,6 +427,9 @@ pub struct Config {
     /// mode describing what file the actual ui output will be compared to
     pub compare_mode: Option<CompareMode>,
 
+    /// Which tests' build and run artifacts to keep around
+    pub keep_artifacts: KeepArtifacts,
+
     // Configuration for various run-make tests frobbing things like C compilers
     // or querying about various LLVM component information.
     pub cc: String,
@@ -250,6 +440,53 @@ pub struct Config {
     pub llvm_components: String,
     pub llvm_cxxflags: String,
     pub nodejs: Option<String>,
+    /// The runtime to run wasm32-unknown-unknown tests with, instead of
+    /// `nodejs` and our shim
+    pub wasm_runtime: Option<String>,
+}
+
+impl Config {
+    /// Where the LLVM tool `name` is: in `llvm_bin_dir` if given, or else in
+    /// `PATH`.
+    pub fn llvm_tool(&self, name: &str) -> Option<PathBuf> {
+        match self.llvm_bin_dir {
+            Some(ref dir) => Some(dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX))),
+            None => util::find_program(Path::new(name)),
+        }
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbosity > 0
+    }
+
+    pub fn is_very_verbose(&self) -> bool {
+        self.verbosity > 1
+    }
+
+    /// The stage of the compiler under test, like `stage1`.
+    pub fn stage(&self) -> &str {
+        self.stage_id.split('-').next().unwrap()
+    }
+
+    /// The names expected output files specific to this target or stage can
+    /// have, from the most specific: the target's triple, its OS and the stage.
+    pub fn output_targets(&self) -> Vec<&str> {
+        let mut targets = vec![&*self.target];
+        targets.extend(util::get_os(&self.target));
+        targets.push(self.stage());
+        targets
+    }
+
+    /// The number of the stage of the compiler under test, if `--stage-id`
+    /// names one.
+    pub fn stage_number(&self) -> Option<u32> {
+        let stage = self.stage();
+        if stage.starts_with("stage") {
+            stage["stage".len()..].parse().ok()
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -259,10 +496,14 @@ pub struct TestPaths {
 }
 
 /// Used by `ui` tests to generate things like `foo.stderr` from `foo.rs`.
+/// Output expected only on some target or stage is in files like
+/// `foo.x86_64-pc-windows-msvc.stderr`, `foo.windows.stderr` or
+/// `foo.stage1.stderr`.
 pub fn expected_output_path(
     testpaths: &TestPaths,
     revision: Option<&str>,
     compare_mode: &Option<CompareMode>,
+    target: Option<&str>,
     kind: &str,
 ) -> PathBuf {
     assert!(UI_EXTENSIONS.contains(&kind));
@@ -274,16 +515,35 @@ pub fn expected_output_path(
     if let Some(ref x) = *compare_mode {
         parts.push(x.to_str());
     }
+    if let Some(x) = target {
+        parts.push(x);
+    }
     parts.push(kind);
 
     let extension = parts.join(".");
     testpaths.file.with_extension(extension)
 }
 
-pub const UI_EXTENSIONS: &[&str] = &[UI_STDERR, UI_STDOUT, UI_FIXED];
+/// How many bytes of each of the stdout and stderr of a process are kept by
+/// default: the first 160 KiB and the last 256 KiB.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 416 * 1024;
+
+pub const UI_EXTENSIONS: &[&str] = &[
+    UI_STDERR,
+    UI_STDOUT,
+    UI_FIXED,
+    UI_RUN_STDERR,
+    UI_RUN_STDOUT,
+    COVERAGE_MAP,
+    COVERAGE_RUN,
+];
 pub const UI_STDERR: &str = "stderr";
 pub const UI_STDOUT: &str = "stdout";
 pub const UI_FIXED: &str = "fixed";
+pub const UI_RUN_STDERR: &str = "run.stderr";
+pub const UI_RUN_STDOUT: &str = "run.stdout";
+pub const COVERAGE_MAP: &str = "cov-map";
+pub const COVERAGE_RUN: &str = "cov-run";
 
 /// Absolute path to the directory where all output for all tests in the given
 /// `relative_dir` group should reside. Example:
@@ -0,0 +1,210 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Collecting the core dumps of processes that crash.
+//!
+//! With `--collect-core-dumps`, the processes tests run may dump core, and when
+//! one crashes, its dump is moved next to the test's `.out` and `.err` files,
+//! where CI can pick it up for post-mortem debugging.
+//!
+//! Where the dump ends up depends on the system. On Linux, it's what
+//! `/proc/sys/kernel/core_pattern` says; only its `%p`, `%e` and `%%` are
+//! understood, and of the patterns piping dumps to a program, only
+//! systemd-coredump's, whose dumps are fetched with `coredumpctl`. macOS puts
+//! dumps in `/cores`, and other systems in the working directory of the
+//! process.
+//!
+//! On Windows, a crashing process only leaves a minidump if Windows Error
+//! Reporting is set up to keep local dumps (the `LocalDumps` registry key),
+//! and those are fetched from their default folder, `%LOCALAPPDATA%\CrashDumps`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use command::Command;
+
+/// The extension of the dump of a crashed process, after the test's.
+pub fn extension() -> &'static str {
+    if cfg!(windows) {
+        "dmp"
+    } else {
+        "core"
+    }
+}
+
+/// Lets the process `command` starts dump core, as far as the hard limit on
+/// the size of core files allows.
+#[cfg(unix)]
+pub fn enable(command: &mut Command) {
+    use libc;
+
+    command.before_exec(|| {
+        unsafe {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if libc::getrlimit(libc::RLIMIT_CORE, &mut limit) == 0 {
+                limit.rlim_cur = limit.rlim_max;
+                libc::setrlimit(libc::RLIMIT_CORE, &limit);
+            }
+        }
+        Ok(())
+    });
+}
+
+#[cfg(not(unix))]
+pub fn enable(_command: &mut Command) {}
+
+/// Whether a process ended with `status` because it crashed.
+#[cfg(unix)]
+pub fn crashed(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal().is_some()
+}
+
+/// Whether a process ended with `status` because it crashed.
+#[cfg(windows)]
+pub fn crashed(status: &ExitStatus) -> bool {
+    // Crashes are reported as an NTSTATUS error code, such as
+    // STATUS_ACCESS_VIOLATION.
+    status
+        .code()
+        .map_or(false, |code| code as u32 & 0xC000_0000 == 0xC000_0000)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn crashed(_status: &ExitStatus) -> bool {
+    false
+}
+
+/// Moves the dump of the crashed process `pid`, which ran `program` in one
+/// of the directories `dirs`, to `dest`. Returns whether there was one.
+///
+/// Dumps whose name doesn't contain the pid, such as a plain `core`, are only
+/// taken from the first directory, which has to belong to the test alone, so
+/// that the dump of a test running in parallel is never picked up.
+pub fn collect(pid: u32, program: &str, dirs: &[&Path], dest: &Path) -> bool {
+    if cfg!(target_os = "linux") {
+        if let Some(pattern) = read_trimmed("/proc/sys/kernel/core_pattern") {
+            if pattern.starts_with('|') {
+                return pattern.contains("systemd-coredump") && coredumpctl(pid, dest);
+            }
+        }
+    }
+    candidates(pid, program, dirs)
+        .into_iter()
+        .any(|path| path.is_file() && move_file(&path, dest))
+}
+
+/// The places the dump of the process `pid` may have been written to.
+fn candidates(pid: u32, program: &str, dirs: &[&Path]) -> Vec<PathBuf> {
+    let name = Path::new(program)
+        .file_name()
+        .map_or(program.to_string(), |name| name.to_string_lossy().into_owned());
+    // The names, and whether they contain the pid.
+    let mut names = Vec::new();
+    if cfg!(target_os = "linux") {
+        let pattern = read_trimmed("/proc/sys/kernel/core_pattern").unwrap_or_default();
+        if let Some(mut path) = expand(&pattern, pid, &name) {
+            let has_pid = pattern.contains("%p") || pattern.contains("%P");
+            let uses_pid = read_trimmed("/proc/sys/kernel/core_uses_pid");
+            if uses_pid.map_or(false, |s| s == "1") && !has_pid {
+                path.push_str(&format!(".{}", pid));
+                names.push((path, true));
+            } else {
+                names.push((path, has_pid));
+            }
+        }
+    } else if cfg!(target_os = "macos") {
+        names.push((format!("/cores/core.{}", pid), true));
+    } else if cfg!(windows) {
+        if let Some(local) = env::var_os("LOCALAPPDATA") {
+            let dir = Path::new(&local).join("CrashDumps");
+            let path = dir.join(format!("{}.{}.dmp", name, pid));
+            names.push((path.to_string_lossy().into_owned(), true));
+        }
+    }
+    names.push((format!("core.{}", pid), true));
+    names.push((format!("{}.core", name), false));
+    names.push(("core".to_string(), false));
+
+    let mut paths = Vec::new();
+    for (name, has_pid) in names {
+        if Path::new(&name).is_absolute() {
+            if has_pid {
+                paths.push(PathBuf::from(name));
+            }
+        } else if has_pid {
+            paths.extend(dirs.iter().map(|dir| dir.join(&name)));
+        } else {
+            paths.extend(dirs.first().map(|dir| dir.join(&name)));
+        }
+    }
+    paths
+}
+
+/// Expands the `core_pattern` `pattern` for the process `pid` running the
+/// program `name`, unless it uses anything else we can't know.
+fn expand(pattern: &str, pid: u32, name: &str) -> Option<String> {
+    let mut path = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => path.push('%'),
+            Some('p') | Some('P') => path.push_str(&pid.to_string()),
+            // The kernel only keeps the first 15 bytes of the name.
+            Some('e') => path.extend(name.chars().scan(0, |len, c| {
+                *len += c.len_utf8();
+                Some(c).filter(|_| *len <= 15)
+            })),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Has systemd-coredump write the dump of the process `pid` to `dest`.
+fn coredumpctl(pid: u32, dest: &Path) -> bool {
+    use std::thread;
+    use std::time::Duration;
+
+    // systemd-coredump may still be busy storing the dump.
+    for _ in 0..10 {
+        let status = Command::new("coredumpctl")
+            .arg("--quiet")
+            .arg("--output")
+            .arg(dest)
+            .args(&["dump", &pid.to_string()])
+            .output()
+            .map(|output| output.status);
+        match status {
+            Ok(ref status) if status.success() => return true,
+            Ok(_) => thread::sleep(Duration::from_millis(500)),
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+fn move_file(from: &Path, to: &Path) -> bool {
+    fs::rename(from, to).is_ok() || (fs::copy(from, to).is_ok() && fs::remove_file(from).is_ok())
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
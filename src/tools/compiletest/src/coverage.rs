@@ -0,0 +1,176 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Coverage reports for `coverage-map` and `coverage-run` tests.
+//!
+//! Tests are built with LLVM's profiling instrumentation (`-C
+//! profile-generate`), which gives every function an array of counters. The
+//! map of a test lists how many counters each of its functions got, read from
+//! the `__profc_` arrays in the LLVM IR of the test. The run report lists the
+//! values of those counters after running the test, as `llvm-profdata show`
+//! prints them from the merged profile.
+//!
+//! Only the functions of the test's own crate are reported, by their path
+//! without the hash that mangled names end in, so that the reports don't change
+//! with the standard library or the compiler's symbol hashes.
+
+use std::collections::BTreeMap;
+
+/// How characters that can't be in symbols are escaped in mangled names.
+const ESCAPES: &[(&str, &str)] = &[
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$RF$", "&"),
+    ("$BP$", "*"),
+    ("$LP$", "("),
+    ("$RP$", ")"),
+    ("$C$", ","),
+    ("$u20$", " "),
+    ("$u7b$", "{"),
+    ("$u7d$", "}"),
+    ("..", "::"),
+];
+
+/// The number of counters of each function of `krate`, from the LLVM IR of an
+/// instrumented build, one function per line.
+pub fn map_report(ir: &str, krate: &str) -> String {
+    let mut functions = BTreeMap::new();
+    for line in ir.lines() {
+        if !line.starts_with("@__profc_") && !line.starts_with("@\"__profc_") {
+            continue;
+        }
+        let name = line.split(" = ").next().unwrap();
+        let counters = line
+            .split(" global [")
+            .nth(1)
+            .and_then(|rest| rest.split(" x i64]").next())
+            .and_then(|n| n.parse::<usize>().ok());
+        if let (Some(path), Some(counters)) = (function_path(name, krate), counters) {
+            functions.insert(path, counters);
+        }
+    }
+    functions
+        .iter()
+        .map(|(path, &counters)| {
+            let plural = if counters == 1 { "" } else { "s" };
+            format!("{}: {} counter{}\n", path, counters, plural)
+        })
+        .collect()
+}
+
+/// The counters of each function of `krate`, from the output of
+/// `llvm-profdata show --all-functions --counts`, one function per line.
+pub fn run_report(show: &str, krate: &str) -> String {
+    let mut functions = BTreeMap::new();
+    // Functions are listed like
+    //
+    //   name:
+    //     Hash: 0x0123456789abcdef
+    //     Counters: 2
+    //     Function count: 1
+    //     Block counts: [0]
+    let mut function = None;
+    for line in show.lines() {
+        let indent = line.len() - line.trim_left().len();
+        let line = line.trim();
+        if indent == 2 && line.ends_with(':') {
+            function = function_path(&line[..line.len() - 1], krate);
+            continue;
+        }
+        let path = match function {
+            Some(ref path) => path.clone(),
+            None => continue,
+        };
+        if line.starts_with("Function count: ") {
+            let count = line["Function count: ".len()..].to_string();
+            functions.insert(path, vec![count]);
+        } else if line.starts_with("Block counts: ") {
+            let counts = line["Block counts: ".len()..].trim_matches(|c| c == '[' || c == ']');
+            let counters = functions.entry(path).or_insert_with(Vec::new);
+            counters.extend(counts.split(", ").filter(|c| !c.is_empty()).map(String::from));
+        }
+    }
+    functions
+        .iter()
+        .map(|(path, counters)| format!("{}: {}\n", path, counters.join(", ")))
+        .collect()
+}
+
+/// The path of the function whose instrumentation or profile is named `name`,
+/// if it's in `krate`. Names are the mangled name of the function, prefixed
+/// with the source file for private ones and with `__profc_` for counters.
+fn function_path(name: &str, krate: &str) -> Option<String> {
+    let mangled = &name[name.find("_ZN")? + 3..];
+    let mut components = Vec::new();
+    let mut rest = mangled.trim_right_matches('"');
+    while !rest.starts_with('E') {
+        let digits = rest.chars().take_while(|c| c.is_digit(10)).count();
+        let len = rest[..digits].parse::<usize>().ok()?;
+        let component = rest.get(digits..digits + len)?;
+        // Components starting with an escape get an underscore in front.
+        components.push(if component.starts_with("_$") { &component[1..] } else { component });
+        rest = &rest[digits + len..];
+    }
+    // The hash that makes the name unique.
+    let is_hash = |s: &str| {
+        s.len() == 17 && s.starts_with('h') && s[1..].chars().all(|c| c.is_digit(16))
+    };
+    if components.last().map_or(false, |c| is_hash(c)) {
+        components.pop();
+    }
+    if components.first() != Some(&krate) {
+        return None;
+    }
+    let path = ESCAPES
+        .iter()
+        .fold(components.join("::"), |path, &(from, to)| path.replace(from, to));
+    Some(path)
+}
+
+#[test]
+fn test_map_report() {
+    let ir = "
+@__profc__ZN3std2rt10lang_start17h481e2e0af9723a8fE = private global [1 x i64] \
+    zeroinitializer, section \"__llvm_prf_cnts\", align 8
+@__profd__ZN5calls4main17h0123456789abcdefE = private global { i64, i64 } { i64 0, i64 0 }
+@__profc__ZN5calls4main17h0123456789abcdefE = private global [1 x i64] zeroinitializer
+@\"__profc_calls.rs:_ZN5calls4main28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE\" = \
+    private global [3 x i64] zeroinitializer
+";
+    assert_eq!(
+        map_report(ir, "calls"),
+        "calls::main: 1 counter\ncalls::main::{{closure}}: 3 counters\n"
+    );
+}
+
+#[test]
+fn test_run_report() {
+    let show = "\
+Counters:
+  calls.rs:_ZN5calls5twice17h0123456789abcdefE:
+    Hash: 0x0000000000000000
+    Counters: 1
+    Function count: 2
+    Block counts: []
+  _ZN3std2rt10lang_start17h481e2e0af9723a8fE:
+    Hash: 0x0000000000000000
+    Counters: 1
+    Function count: 1
+    Block counts: []
+  calls.rs:_ZN5calls6branch17h0123456789abcdefE:
+    Hash: 0x0123456789abcdef
+    Counters: 3
+    Function count: 1
+    Block counts: [1, 0]
+Instrumentation level: IR
+Functions shown: 3
+";
+    assert_eq!(run_report(show, "calls"), "calls::branch: 1, 1, 0\ncalls::twice: 2\n");
+}
@@ -0,0 +1,311 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rust code blocks in Markdown files, for `doc-examples` tests.
+//!
+//! Code blocks are found and marked up the way rustdoc does it: a fenced block
+//! is Rust code unless its info string names something else, it can be tagged
+//! with `ignore`, `no_run`, `should_panic`, `compile_fail` and `editionYYYY`,
+//! and lines starting with `# ` are hidden from the reader but still part of
+//! the code.
+
+use regex::Regex;
+
+/// A code block to build and run.
+#[derive(Debug)]
+pub struct Example {
+    /// The line of the Markdown file the block starts at.
+    pub line: usize,
+    pub code: String,
+    pub no_run: bool,
+    pub should_panic: bool,
+    pub compile_fail: bool,
+    pub edition: Option<String>,
+}
+
+impl Example {
+    /// The example as a program of its own, with a `main` around it unless it
+    /// has one already.
+    pub fn program(&self) -> String {
+        let mut program = String::from("#![allow(unused)]\n");
+        if has_main(&self.code) {
+            program.push_str(&self.code);
+            return program;
+        }
+        // Crate attributes have to stay outside of `main`.
+        let (attrs, body): (Vec<&str>, Vec<&str>) = self
+            .code
+            .lines()
+            .partition(|line| line.trim_left().starts_with("#!["));
+        for line in attrs {
+            program.push_str(line);
+            program.push('\n');
+        }
+        program.push_str("fn main() {\n");
+        for line in body {
+            program.push_str(line);
+            program.push('\n');
+        }
+        program.push_str("}\n");
+        program
+    }
+}
+
+/// Whether `code` defines `fn main` as an item of its own, rather than inside
+/// some other item, a comment or a string.
+fn has_main(code: &str) -> bool {
+    let main = Regex::new(r"\bfn\s+main\s*\(").unwrap();
+    main.is_match(&top_level(code))
+}
+
+/// `code` with comments, literals and whatever is between braces blanked out,
+/// leaving the outermost items in view. This isn't a full lexer, but enough
+/// for what examples look like.
+fn top_level(code: &str) -> String {
+    let chars = code.chars().collect::<Vec<_>>();
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut out = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let after_ident = i > 0 && is_ident(chars[i - 1]);
+        // Where the token starting at `i` ends.
+        let end = if c == '/' && next == Some('/') {
+            chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |n| i + n)
+        } else if c == '/' && next == Some('*') {
+            let mut nesting = 0;
+            let mut j = i;
+            loop {
+                match (chars.get(j), chars.get(j + 1)) {
+                    (Some(&'/'), Some(&'*')) => {
+                        nesting += 1;
+                        j += 2;
+                    }
+                    (Some(&'*'), Some(&'/')) => {
+                        nesting -= 1;
+                        j += 2;
+                        if nesting == 0 {
+                            break j;
+                        }
+                    }
+                    (Some(_), _) => j += 1,
+                    (None, _) => break j,
+                }
+            }
+        } else if c == 'r' && !after_ident && (next == Some('"') || next == Some('#')) {
+            let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+            if chars.get(i + 1 + hashes) == Some(&'"') {
+                let mut j = i + hashes + 2;
+                while j < chars.len()
+                    && !(chars[j] == '"'
+                        && chars[j + 1..].iter().take(hashes).filter(|&&c| c == '#').count()
+                            == hashes)
+                {
+                    j += 1;
+                }
+                (j + 1 + hashes).min(chars.len())
+            } else {
+                i + 1
+            }
+        } else if c == '"' {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != '"' {
+                j += if chars[j] == '\\' { 2 } else { 1 };
+            }
+            (j + 1).min(chars.len())
+        } else if c == '\'' && next == Some('\\') {
+            let mut j = i + 2;
+            while j < chars.len() && chars[j] != '\'' {
+                j += 1;
+            }
+            (j + 1).min(chars.len())
+        } else if c == '\'' && chars.get(i + 2) == Some(&'\'') {
+            i + 3
+        } else {
+            match c {
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            out.push(if depth == 0 && c != '}' { c } else { ' ' });
+            i += 1;
+            continue;
+        };
+        out.push(' ');
+        i = end;
+    }
+    out
+}
+
+/// The Rust code blocks in `markdown` that aren't tagged with `ignore`.
+pub fn extract(markdown: &str) -> Vec<Example> {
+    let mut examples = Vec::new();
+    // The fence of the block we're in, and the example it is, if it's one.
+    let mut block: Option<(String, Option<Example>)> = None;
+    for (i, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_left();
+        let fence_len = match trimmed.chars().next() {
+            Some(c @ '`') | Some(c @ '~') => trimmed.chars().take_while(|&d| d == c).count(),
+            _ => 0,
+        };
+        let fence = &trimmed[..fence_len];
+        let is_fence = fence_len >= 3;
+
+        match block.take() {
+            None => {
+                if is_fence {
+                    let example = example(&trimmed[fence_len..], i + 1);
+                    block = Some((fence.to_string(), example));
+                }
+            }
+            Some((open, example)) => {
+                let closes = fence.starts_with(&open[..]) && trimmed[fence_len..].trim().is_empty();
+                if is_fence && closes {
+                    examples.extend(example);
+                } else {
+                    let example = example.map(|mut example| {
+                        example.code.push_str(unhide(line));
+                        example.code.push('\n');
+                        example
+                    });
+                    block = Some((open, example));
+                }
+            }
+        }
+    }
+    // Like in Markdown, an unclosed block runs to the end of the file.
+    if let Some((_, example)) = block {
+        examples.extend(example);
+    }
+    examples
+}
+
+/// The example for a block with the info string `info`, if it is Rust code
+/// that isn't ignored.
+fn example(info: &str, line: usize) -> Option<Example> {
+    let mut example = Example {
+        line,
+        code: String::new(),
+        no_run: false,
+        should_panic: false,
+        compile_fail: false,
+        edition: None,
+    };
+    let tokens = info
+        .split(|c: char| c == ',' || c == ' ' || c == '\t')
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    for token in tokens {
+        match token {
+            "rust" | "allow_fail" | "test_harness" => {}
+            "ignore" => return None,
+            "no_run" => example.no_run = true,
+            "should_panic" => example.should_panic = true,
+            "compile_fail" => example.compile_fail = true,
+            _ if token.starts_with("edition") => {
+                example.edition = Some(token["edition".len()..].to_string());
+            }
+            // Some other language.
+            _ => return None,
+        }
+    }
+    Some(example)
+}
+
+/// `line` with the `#` that hides it from the reader removed.
+fn unhide(line: &str) -> &str {
+    let trimmed = line.trim_left();
+    if trimmed == "#" {
+        ""
+    } else if trimmed.starts_with("# ") {
+        &trimmed[2..]
+    } else {
+        line
+    }
+}
+
+#[test]
+fn test_extract() {
+    let markdown = "\
+Some text.
+
+```
+let x = 1;
+# let y = x;
+#
+```
+
+```rust,should_panic,edition2018
+panic!();
+```
+
+```text
+not Rust
+```
+
+~~~ignore
+neither
+~~~
+
+````no_run
+```
+nested fence
+````
+
+```compile_fail
+let x: u8 = \"\";
+";
+    let examples = extract(markdown);
+    assert_eq!(examples.len(), 4);
+
+    assert_eq!(examples[0].line, 3);
+    assert_eq!(examples[0].code, "let x = 1;\nlet y = x;\n\n");
+    assert!(!examples[0].no_run && !examples[0].should_panic && !examples[0].compile_fail);
+
+    assert_eq!(examples[1].line, 9);
+    assert!(examples[1].should_panic);
+    assert_eq!(examples[1].edition, Some("2018".to_string()));
+
+    assert!(examples[2].no_run);
+    assert_eq!(examples[2].code, "```\nnested fence\n");
+
+    // An unclosed block runs to the end of the file.
+    assert!(examples[3].compile_fail);
+    assert_eq!(examples[3].code, "let x: u8 = \"\";\n");
+}
+
+#[test]
+fn test_program() {
+    let example = |code: &str| Example {
+        line: 1,
+        code: code.to_string(),
+        no_run: false,
+        should_panic: false,
+        compile_fail: false,
+        edition: None,
+    };
+    assert_eq!(
+        example("#![feature(never_type)]\nlet x = 1;\n").program(),
+        "#![allow(unused)]\n#![feature(never_type)]\nfn main() {\nlet x = 1;\n}\n"
+    );
+    let has_main = "fn main () {\n    run();\n}\n";
+    assert_eq!(example(has_main).program(), format!("#![allow(unused)]\n{}", has_main));
+
+    let wrapped = |code: &str| example(code).program().contains("\nfn main() {\n");
+    assert!(!wrapped("pub fn main() {}\n"));
+    assert!(!wrapped("#[test]\nfn x() {}\nfn\nmain() {}\n"));
+    assert!(wrapped("fn main_loop() {}\nmain_loop();\n"));
+    assert!(wrapped("// fn main() {}\n/* fn main() {} */\nlet x = 1;\n"));
+    assert!(wrapped("let s = \"fn main() {}\";\nlet r = r#\"fn main() {\"#;\n"));
+    assert!(wrapped("mod m {\n    fn main() {}\n}\n"));
+    assert!(!wrapped("let c = '{';\nfn f<'a>(x: &'a str) {}\nfn main() {}\n"));
+}
@@ -18,47 +18,103 @@ use common::{self, CompareMode, Config, Mode};
 use util;
 
 use extract_gdb_version;
+use extract_llvm_version;
 
 /// Properties which must be known very early, before actually running
 /// the test.
 pub struct EarlyProps {
     pub ignore: bool,
+    // The header line that caused the test to be ignored, if any
+    pub ignore_reason: Option<String>,
     pub should_fail: bool,
     pub aux: Vec<String>,
     pub revisions: Vec<String>,
+    pub flaky: bool,
+    pub run_input: Option<String>,
+    // The tests in the same directory this one has to run after
+    pub after: Vec<String>,
 }
 
 impl EarlyProps {
     pub fn from_file(config: &Config, testfile: &Path) -> Self {
         let mut props = EarlyProps {
             ignore: false,
+            ignore_reason: None,
             should_fail: false,
             aux: Vec::new(),
             revisions: vec![],
+            flaky: false,
+            run_input: None,
+            after: Vec::new(),
         };
 
-        iter_header(testfile, None, &mut |ln| {
+        iter_header(config, testfile, None, &mut |ln| {
             // we should check if any only-<platform> exists and if it exists
             // and does not matches the current platform, skip the test
-            props.ignore = props.ignore || config.parse_cfg_name_directive(ln, "ignore")
+            if !props.ignore && (config.parse_cfg_name_directive(ln, "ignore")
                 || (config.has_cfg_prefix(ln, "only")
                     && !config.parse_cfg_name_directive(ln, "only"))
                 || ignore_gdb(config, ln) || ignore_lldb(config, ln)
-                || ignore_llvm(config, ln);
+                || ignore_llvm(config, ln) || ignore_build_profile(config, ln)
+                || ignore_stage(config, ln))
+            {
+                props.ignore = true;
+                props.ignore_reason = Some(ln.trim().to_string());
+            }
 
             if let Some(s) = config.parse_aux_build(ln) {
                 props.aux.push(s);
             }
+            if let Some(s) = config.parse_aux_host_build(ln, "aux-proc-macro") {
+                props.aux.push(s);
+            }
+            if let Some(s) = config.parse_aux_host_build(ln, "aux-plugin") {
+                props.aux.push(s);
+            }
 
             if let Some(r) = config.parse_revisions(ln) {
                 props.revisions.extend(r);
             }
+            if let Some(matrix) = config.parse_flag_matrix(ln) {
+                props.revisions.extend(matrix.into_iter().map(|(name, _)| name));
+            }
 
             props.should_fail = props.should_fail || config.parse_name_directive(ln, "should-fail");
+            props.flaky = props.flaky || config.parse_name_directive(ln, "flaky");
+
+            if let Some(input) = config.parse_run_input(ln) {
+                props.run_input = Some(input);
+            }
+            if let Some(after) = config.parse_name_value_directive(ln, "after") {
+                props.after.extend(after.split_whitespace().map(str::to_string));
+            }
         });
 
+        // remote-test-server runs the program with a null stdin.
+        if props.run_input.is_some() && config.remote_test_client.is_some() && !props.ignore {
+            props.ignore = true;
+            props.ignore_reason = Some("run-input needs a local stdin".to_string());
+        }
+
         return props;
 
+        fn ignore_build_profile(config: &Config, line: &str) -> bool {
+            (config.debug_assertions && config.parse_name_directive(line, "ignore-debug"))
+                || (config.optimize_tests && config.parse_name_directive(line, "ignore-optimized"))
+        }
+
+        fn ignore_stage(config: &Config, line: &str) -> bool {
+            let min_stage = match config.parse_name_value_directive(line, "min-stage") {
+                Some(stage) => stage
+                    .trim()
+                    .trim_left_matches("stage")
+                    .parse::<u32>()
+                    .expect("Malformed min-stage directive"),
+                None => return false,
+            };
+            config.stage_number().map_or(false, |stage| stage < min_stage)
+        }
+
         fn ignore_gdb(config: &Config, line: &str) -> bool {
             if config.mode != common::DebugInfoGdb {
                 return false;
@@ -145,23 +201,19 @@ impl EarlyProps {
             if config.system_llvm && line.starts_with("no-system-llvm") {
                 return true;
             }
-            if let Some(ref actual_version) = config.llvm_version {
+            if let Some(needed) = config.parse_name_value_directive(line, "needs-llvm-components") {
+                let available: Vec<&str> = config.llvm_components.split_whitespace().collect();
+                return needed.split_whitespace().any(|c| !available.contains(&c));
+            }
+            if let Some(actual_version) = config.llvm_version {
                 if line.starts_with("min-llvm-version") {
-                    let min_version = line.trim_right()
-                        .rsplit(' ')
-                        .next()
-                        .expect("Malformed llvm version directive");
                     // Ignore if actual version is smaller the minimum required
                     // version
-                    &actual_version[..] < min_version
+                    actual_version < extract_min_llvm_version(line)
                 } else if line.starts_with("min-system-llvm-version") {
-                    let min_version = line.trim_right()
-                        .rsplit(' ')
-                        .next()
-                        .expect("Malformed llvm version directive");
                     // Ignore if using system LLVM and actual version
                     // is smaller the minimum required version
-                    config.system_llvm && &actual_version[..] < min_version
+                    config.system_llvm && actual_version < extract_min_llvm_version(line)
                 } else {
                     false
                 }
@@ -169,13 +221,54 @@ impl EarlyProps {
                 false
             }
         }
+
+        fn extract_min_llvm_version(line: &str) -> u32 {
+            line.trim_right()
+                .rsplit(' ')
+                .next()
+                .and_then(extract_llvm_version)
+                .expect("Malformed llvm version directive")
+        }
     }
 }
 
+/// How the `error-pattern`s of a test have to show up in its output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPatternMode {
+    /// Every pattern, on a later line than the one before it
+    Ordered,
+    /// Every pattern, anywhere
+    Unordered,
+    /// Every pattern, on as many lines as it is given
+    Exact,
+}
+
+/// Which output of a test `error-pattern` and `forbid-output` look at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckedOutput {
+    Stdout,
+    Stderr,
+    /// Stdout and stderr, each matched on its own
+    Both,
+}
+
+/// How far a test that is expected to pass has to get.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassMode {
+    /// Typecheck without generating code
+    Check,
+    /// Build, but don't run
+    Build,
+    /// Build and run successfully
+    Run,
+}
+
 #[derive(Clone, Debug)]
 pub struct TestProps {
     // Lines that should be expected, in order, on standard out
     pub error_patterns: Vec<String>,
+    // How to match `error_patterns` against the output
+    pub error_pattern_mode: ErrorPatternMode,
     // Extra flags to pass to the compiler
     pub compile_flags: Vec<String>,
     // Extra flags to pass when the compiled code is run (such as --bench)
@@ -187,6 +280,16 @@ pub struct TestProps {
     // directory as the test, but for backwards compatibility reasons
     // we also check the auxiliary directory)
     pub aux_builds: Vec<String>,
+    // Auxiliary crates to build for the host as proc macros or as compiler
+    // plugins respectively, so that the test can load them even when it's
+    // compiled for another target
+    pub aux_proc_macros: Vec<String>,
+    pub aux_plugins: Vec<String>,
+    // C files, relative to the test, to build into static libraries the test
+    // is linked against
+    pub aux_native_libs: Vec<String>,
+    // Extra arguments for the linker
+    pub link_flags: Vec<String>,
     // Environment settings to use for compiling
     pub rustc_env: Vec<(String, String)>,
     // Environment settings to use during execution
@@ -197,8 +300,9 @@ pub struct TestProps {
     pub build_aux_docs: bool,
     // Flag to force a crate to be built with the host architecture
     pub force_host: bool,
-    // Check stdout for error-pattern output as well as stderr
-    pub check_stdout: bool,
+    // The output to check for error-pattern and forbid-output, set with
+    // `check-output`; `check-stdout` is kept as another way to ask for both
+    pub checked_output: CheckedOutput,
     // Don't force a --crate-type=dylib flag on the command line
     pub no_prefer_dynamic: bool,
     // Run --pretty expanded when running pretty printing tests
@@ -207,6 +311,9 @@ pub struct TestProps {
     pub pretty_mode: String,
     // Only compare pretty output and don't try compiling
     pub pretty_compare_only: bool,
+    // Also build and run the pretty-printed source, and check that it behaves
+    // like the original program
+    pub pretty_run: bool,
     // Patterns which must not appear in the output of a cfail test.
     pub forbid_output: Vec<String>,
     // Revisions to test for incremental compilation.
@@ -224,45 +331,117 @@ pub struct TestProps {
     pub run_pass: bool,
     // Skip any codegen step and running the executable. Only for run-pass.
     pub skip_codegen: bool,
+    // How far the test has to get, given with `pass-mode`. Sets `compile_pass`,
+    // `run_pass` and `skip_codegen` accordingly.
+    pub pass_mode: Option<PassMode>,
     // Do not pass `-Z ui-testing` to UI tests
     pub disable_ui_testing_normalization: bool,
     // customized normalization rules
     pub normalize_stdout: Vec<(String, String)>,
     pub normalize_stderr: Vec<(String, String)>,
+    // The exact status a failing test has to exit with, set with either
+    // `failure-status` or `exit-code`
     pub failure_status: i32,
+    // The signal (e.g. `SIGSEGV`) a failing test has to be terminated by,
+    // instead of exiting with `failure_status`
+    pub failure_signal: Option<String>,
     pub run_rustfix: bool,
+    // Only apply the suggestions rustc marks as machine-applicable when
+    // running rustfix
+    pub rustfix_only_machine_applicable: bool,
+    // Kill the compiler or the test after this many seconds, overriding
+    // `--timeout`
+    pub timeout: Option<u64>,
+    // Fail if the compiler or the test program needs more than this many MiB
+    // of memory at once
+    pub max_rss: Option<u64>,
+    // Fail if the binary built from the test is bigger than this many bytes
+    pub max_binary_size: Option<u64>,
+    // How to produce the assembly checked by an assembly test
+    pub assembly_output: Option<String>,
+    // Compare the output of the compiled program against `*.run.stdout` and
+    // `*.run.stderr`
+    pub check_run_results: bool,
+    // The compiler is expected to crash with an internal compiler error. Lets
+    // us keep reproducers of known ICEs and notice once they get fixed.
+    pub should_ice: bool,
+    // The issue (e.g. `#12345`) of a bug whose current, wrong behavior this
+    // test records. The test fails once that behavior changes.
+    pub known_bug: Option<String>,
+    // Run the compiled program under this command instead of `--runtool`, or
+    // directly if it is `none`
+    pub runtool: Option<String>,
+    // A file, relative to the test, whose contents are fed to the compiled
+    // program on its standard input
+    pub run_input: Option<String>,
+    // Don't fail the test for warnings when run with `--deny-warnings`
+    pub allow_warnings: bool,
+    // Build the test even when run with `--compile-fail-metadata`, because the
+    // errors it expects only show up during code generation
+    pub needs_codegen: bool,
+    // Extra artifacts (e.g. `llvm-ir`) to have the compiler emit, each of
+    // which has to come out non-empty
+    pub emit: Vec<String>,
+    // Symbols the binary built from the test has to have: each has to be part
+    // of the name of one of them
+    pub check_symbols: Vec<String>,
+    // Symbols the binary built from the test mustn't have, in the same way
+    pub check_no_symbols: Vec<String>,
 }
 
 impl TestProps {
     pub fn new() -> Self {
         TestProps {
             error_patterns: vec![],
+            error_pattern_mode: ErrorPatternMode::Ordered,
             compile_flags: vec![],
             run_flags: None,
             pp_exact: None,
             aux_builds: vec![],
+            aux_proc_macros: vec![],
+            aux_plugins: vec![],
+            aux_native_libs: vec![],
+            link_flags: vec![],
             revisions: vec![],
             rustc_env: vec![],
             exec_env: vec![],
             check_lines: vec![],
             build_aux_docs: false,
             force_host: false,
-            check_stdout: false,
+            checked_output: CheckedOutput::Stderr,
             no_prefer_dynamic: false,
             pretty_expanded: false,
             pretty_mode: "normal".to_string(),
             pretty_compare_only: false,
+            pretty_run: false,
             forbid_output: vec![],
             incremental_dir: None,
             compile_pass: false,
             check_test_line_numbers_match: false,
             run_pass: false,
             skip_codegen: false,
+            pass_mode: None,
             disable_ui_testing_normalization: false,
             normalize_stdout: vec![],
             normalize_stderr: vec![],
             failure_status: -1,
+            failure_signal: None,
             run_rustfix: false,
+            rustfix_only_machine_applicable: false,
+            timeout: None,
+            max_rss: None,
+            max_binary_size: None,
+            assembly_output: None,
+            check_run_results: false,
+            should_ice: false,
+            known_bug: None,
+            runtool: None,
+            run_input: None,
+            allow_warnings: false,
+            needs_codegen: false,
+            emit: vec![],
+            check_symbols: vec![],
+            check_no_symbols: vec![],
         }
     }
 
@@ -287,11 +466,24 @@ impl TestProps {
     /// `//[foo]`), then the property is ignored unless `cfg` is
     /// `Some("foo")`.
     fn load_from(&mut self, testfile: &Path, cfg: Option<&str>, config: &Config) {
-        iter_header(testfile, cfg, &mut |ln| {
+        iter_header(config, testfile, cfg, &mut |ln| {
             if let Some(ep) = config.parse_error_pattern(ln) {
                 self.error_patterns.push(ep);
             }
 
+            if let Some(eps) = config.parse_name_value_directive(ln, "error-patterns") {
+                self.error_patterns.extend(
+                    eps.split(',')
+                        .map(|ep| ep.trim())
+                        .filter(|ep| !ep.is_empty())
+                        .map(|ep| ep.to_string()),
+                );
+            }
+
+            if let Some(mode) = config.parse_error_pattern_mode(ln) {
+                self.error_pattern_mode = mode;
+            }
+
             if let Some(flags) = config.parse_compile_flags(ln) {
                 self.compile_flags
                     .extend(flags.split_whitespace().map(|s| s.to_owned()));
@@ -305,6 +497,17 @@ impl TestProps {
                 self.revisions.extend(r);
             }
 
+            // Every combination of flags is a revision of its own, built
+            // with those flags.
+            if let Some(matrix) = config.parse_flag_matrix(ln) {
+                for (name, flags) in matrix {
+                    if cfg == Some(&name[..]) {
+                        self.compile_flags.extend(flags);
+                    }
+                    self.revisions.push(name);
+                }
+            }
+
             if self.run_flags.is_none() {
                 self.run_flags = config.parse_run_flags(ln);
             }
@@ -321,8 +524,8 @@ impl TestProps {
                 self.force_host = config.parse_force_host(ln);
             }
 
-            if !self.check_stdout {
-                self.check_stdout = config.parse_check_stdout(ln);
+            if let Some(output) = config.parse_checked_output(ln) {
+                self.checked_output = output;
             }
 
             if !self.no_prefer_dynamic {
@@ -341,10 +544,31 @@ impl TestProps {
                 self.pretty_compare_only = config.parse_pretty_compare_only(ln);
             }
 
+            if !self.pretty_run {
+                self.pretty_run = config.parse_name_directive(ln, "pretty-run");
+            }
+
             if let Some(ab) = config.parse_aux_build(ln) {
                 self.aux_builds.push(ab);
             }
 
+            if let Some(ab) = config.parse_aux_host_build(ln, "aux-proc-macro") {
+                self.aux_proc_macros.push(ab);
+            }
+
+            if let Some(ab) = config.parse_aux_host_build(ln, "aux-plugin") {
+                self.aux_plugins.push(ab);
+            }
+
+            if let Some(lib) = config.parse_name_value_directive(ln, "aux-native-lib") {
+                self.aux_native_libs.push(lib.trim().to_string());
+            }
+
+            if let Some(flags) = config.parse_name_value_directive(ln, "link-flags") {
+                self.link_flags
+                    .extend(flags.split_whitespace().map(|s| s.to_owned()));
+            }
+
             if let Some(ee) = config.parse_env(ln, "exec-env") {
                 self.exec_env.push(ee);
             }
@@ -378,6 +602,10 @@ impl TestProps {
                 self.skip_codegen = config.parse_skip_codegen(ln);
             }
 
+            if let Some(mode) = config.parse_pass_mode(ln) {
+                self.pass_mode = Some(mode);
+            }
+
             if !self.disable_ui_testing_normalization {
                 self.disable_ui_testing_normalization =
                     config.parse_disable_ui_testing_normalization(ln);
@@ -394,11 +622,99 @@ impl TestProps {
                 self.failure_status = code;
             }
 
+            if let Some(signal) = config.parse_name_value_directive(ln, "failure-signal") {
+                self.failure_signal = Some(signal.trim().to_string());
+            }
+
             if !self.run_rustfix {
                 self.run_rustfix = config.parse_run_rustfix(ln);
             }
+
+            if !self.rustfix_only_machine_applicable {
+                self.rustfix_only_machine_applicable =
+                    config.parse_name_directive(ln, "rustfix-only-machine-applicable");
+                // Only applying some suggestions implies applying them at all.
+                self.run_rustfix |= self.rustfix_only_machine_applicable;
+            }
+
+            if let Some(secs) = config.parse_timeout(ln) {
+                self.timeout = Some(secs);
+            }
+
+            if let Some(mib) = config.parse_max_rss(ln) {
+                self.max_rss = Some(mib);
+            }
+
+            if let Some(bytes) = config.parse_max_binary_size(ln) {
+                self.max_binary_size = Some(bytes);
+            }
+
+            if self.assembly_output.is_none() {
+                self.assembly_output = config.parse_assembly_output(ln);
+            }
+
+            if !self.check_run_results {
+                self.check_run_results = config.parse_name_directive(ln, "check-run-results");
+            }
+
+            if !self.should_ice {
+                self.should_ice = config.parse_name_directive(ln, "should-ice");
+            }
+
+            if self.known_bug.is_none() {
+                self.known_bug = config.parse_known_bug(ln, testfile);
+            }
+
+            if let Some(tool) = config.parse_name_value_directive(ln, "runtool") {
+                self.runtool = Some(tool.trim().to_string());
+            }
+
+            if let Some(input) = config.parse_run_input(ln) {
+                self.run_input = Some(input);
+            }
+
+            if !self.allow_warnings {
+                self.allow_warnings = config.parse_name_directive(ln, "allow-warnings");
+            }
+
+            if !self.needs_codegen {
+                self.needs_codegen = config.parse_name_directive(ln, "needs-codegen");
+            }
+
+            if let Some(kinds) = config.parse_name_value_directive(ln, "emit") {
+                for kind in kinds.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                    if emit_extension(kind).is_none() {
+                        panic!(
+                            "unknown artifact `{}` in `emit` header, expected one of `asm`, \
+                             `llvm-bc`, `llvm-ir`, `obj`, `metadata`, `mir` or `dep-info`",
+                            kind
+                        );
+                    }
+                    self.emit.push(kind.to_string());
+                }
+            }
+
+            if let Some(symbol) = config.parse_name_value_directive(ln, "check-symbol") {
+                self.check_symbols.push(symbol.trim().to_string());
+            }
+            if let Some(symbol) = config.parse_name_value_directive(ln, "check-no-symbol") {
+                self.check_no_symbols.push(symbol.trim().to_string());
+            }
         });
 
+        match self.pass_mode {
+            Some(PassMode::Check) => {
+                self.compile_pass = true;
+                self.skip_codegen = true;
+            }
+            Some(PassMode::Build) => self.compile_pass = true,
+            Some(PassMode::Run) => {
+                self.compile_pass = true;
+                self.run_pass = true;
+            }
+            None => {}
+        }
+
         if self.failure_status == -1 {
             self.failure_status = match config.mode {
                 Mode::RunFail => 101,
@@ -416,8 +732,82 @@ impl TestProps {
     }
 }
 
-fn iter_header(testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
-    if testfile.is_dir() {
+/// The extension of the file the compiler writes an `--emit` artifact of the
+/// given kind to, for the kinds the `emit` header accepts.
+pub fn emit_extension(kind: &str) -> Option<&'static str> {
+    match kind {
+        "asm" => Some("s"),
+        "llvm-bc" => Some("bc"),
+        "llvm-ir" => Some("ll"),
+        "obj" => Some("o"),
+        "metadata" => Some("rmeta"),
+        "mir" => Some("mir"),
+        "dep-info" => Some("d"),
+        _ => None,
+    }
+}
+
+/// The header lines of a test that apply to the given revision, without the
+/// leading comment.
+pub fn header_lines(config: &Config, testfile: &Path, cfg: Option<&str>) -> Vec<String> {
+    let mut lines = Vec::new();
+    iter_header(config, testfile, cfg, &mut |ln| lines.push(ln.trim().to_string()));
+    lines
+}
+
+/// The name of the file holding the default headers of the tests in a
+/// directory and all of its subdirectories.
+pub const DIR_HEADERS: &str = "compiletest-headers";
+
+/// The `compiletest-headers` files that apply to `testfile`, outermost first.
+/// Auxiliary files and files outside of the test suite have none.
+pub fn dir_header_files(config: &Config, testfile: &Path) -> Vec<PathBuf> {
+    let relative = match testfile.strip_prefix(&config.src_base) {
+        Ok(relative) => relative,
+        Err(_) => return Vec::new(),
+    };
+    if relative.components().any(|c| c.as_os_str() == "auxiliary") {
+        return Vec::new();
+    }
+    let mut files = testfile
+        .ancestors()
+        .skip(1)
+        .take(relative.components().count())
+        .map(|dir| dir.join(DIR_HEADERS))
+        .filter(|file| file.is_file())
+        .collect::<Vec<_>>();
+    files.reverse();
+    files
+}
+
+/// Calls `it` with every header line of `testfile`, leaving out the ones
+/// suppressed with `--suppress-header` and adding the ones forced with
+/// `--force-header`. The headers of the `compiletest-headers` files in the
+/// directories above `testfile` come first, as if they were written at the
+/// top of the test. Run-make tests, which are directories, only get the forced
+/// headers.
+fn iter_header(config: &Config, testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
+    if !testfile.is_dir() {
+        let mut filtered = |ln: &str| {
+            let name = ln.split(|c: char| c == ':' || c.is_whitespace()).next().unwrap();
+            if !config.suppressed_headers.iter().any(|s| s == name) {
+                it(ln);
+            }
+        };
+        for file in dir_header_files(config, testfile) {
+            iter_file_header(&file, cfg, &mut filtered);
+        }
+        iter_file_header(testfile, cfg, &mut filtered);
+    }
+    for ln in &config.forced_headers {
+        it(ln);
+    }
+}
+
+fn iter_file_header(testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
+    // Markdown files for `doc-examples` tests have no headers of their own;
+    // their code blocks are tagged instead.
+    if testfile.extension().map_or(false, |e| e == "md") {
         return;
     }
 
@@ -429,6 +819,10 @@ fn iter_header(testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
 
     let comment_with_brace = comment.to_string() + "[";
 
+    // A directive ending in `\` continues on the next comment line; this holds
+    // the directive so far and whether it applies to `cfg`.
+    let mut continued: Option<(String, bool)> = None;
+
     let rdr = BufReader::new(File::open(testfile).unwrap());
     for ln in rdr.lines() {
         // Assume that any directives will be found before the first
@@ -436,7 +830,26 @@ fn iter_header(testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
         // with a warm page cache. Maybe with a cold one.
         let ln = ln.unwrap();
         let ln = ln.trim();
-        if ln.starts_with("fn") || ln.starts_with("mod") {
+        if let Some((mut directive, matches)) = continued.take() {
+            if !ln.starts_with(comment) {
+                panic!("malformed header in {}: `{}` is continued with `\\`, \
+                        but the next line is not a comment",
+                        testfile.display(), directive);
+            }
+            let mut rest = ln[comment.len()..].trim_left();
+            if rest.starts_with('[') {
+                if let Some(close_brace) = rest.find(']') {
+                    rest = rest[close_brace + 1..].trim_left();
+                }
+            }
+            directive.push(' ');
+            directive.push_str(rest.trim_right_matches('\\').trim_right());
+            if rest.ends_with('\\') {
+                continued = Some((directive, matches));
+            } else if matches {
+                it(&directive);
+            }
+        } else if ln.starts_with("fn") || ln.starts_with("mod") {
             return;
         } else if ln.starts_with(&comment_with_brace) {
             // A comment like `//[foo]` is specific to revision `foo`
@@ -447,18 +860,61 @@ fn iter_header(testfile: &Path, cfg: Option<&str>, it: &mut dyn FnMut(&str)) {
                     Some(s) => s == &lncfg[..],
                     None => false,
                 };
-                if matches {
-                    it(ln[(close_brace + 1)..].trim_left());
+                let directive = ln[(close_brace + 1)..].trim_left();
+                if is_continued(directive) {
+                    continued = Some((directive.trim_right_matches('\\').trim_right().to_string(),
+                                      matches));
+                } else if matches {
+                    it(directive);
                 }
             } else {
                 panic!("malformed condition directive: expected `{}foo]`, found `{}`",
                         comment_with_brace, ln)
             }
         } else if ln.starts_with(comment) {
-            it(ln[comment.len() ..].trim_left());
+            let directive = ln[comment.len() ..].trim_left();
+            if is_continued(directive) {
+                continued = Some((directive.trim_right_matches('\\').trim_right().to_string(),
+                                  true));
+            } else {
+                it(directive);
+            }
+        }
+    }
+    if let Some((directive, _)) = continued {
+        panic!("malformed header in {}: `{}` is continued with `\\` at the end of the file",
+               testfile.display(), directive);
+    }
+}
+
+/// Whether `ln` is a `name: value` directive whose value continues on the
+/// next line.
+fn is_continued(ln: &str) -> bool {
+    let name_len = ln
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(ln.len());
+    name_len > 0 && ln[name_len..].starts_with(':') && ln.ends_with('\\')
+}
+
+/// A name for a set of flags that can be used as a `--cfg` and in file names,
+/// like `C_opt_level_0` for `-C opt-level=0`.
+fn flags_name(flags: &str) -> String {
+    let mut name = String::new();
+    for c in flags.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
         }
     }
-    return;
+    let name = name.trim_right_matches('_');
+    if name.is_empty() {
+        "none".to_string()
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
 }
 
 impl Config {
@@ -466,6 +922,30 @@ impl Config {
         self.parse_name_value_directive(line, "error-pattern")
     }
 
+    fn parse_error_pattern_mode(&self, line: &str) -> Option<ErrorPatternMode> {
+        self.parse_name_value_directive(line, "error-pattern-mode")
+            .map(|mode| match mode.trim() {
+                "ordered" => ErrorPatternMode::Ordered,
+                "unordered" => ErrorPatternMode::Unordered,
+                "exact" => ErrorPatternMode::Exact,
+                mode => panic!("unknown error-pattern-mode `{}`", mode),
+            })
+    }
+
+    fn parse_pass_mode(&self, line: &str) -> Option<PassMode> {
+        self.parse_name_value_directive(line, "pass-mode")
+            .map(|mode| match mode.trim() {
+                "check" => PassMode::Check,
+                "build" => PassMode::Build,
+                "run" => match self.mode {
+                    Mode::RunPass | Mode::RunPassValgrind | Mode::Ui => PassMode::Run,
+                    _ => panic!("`pass-mode: run` doesn't work in {} tests, which are never run",
+                                self.mode),
+                },
+                mode => panic!("unknown pass-mode `{}`", mode),
+            })
+    }
+
     fn parse_forbid_output(&self, line: &str) -> Option<String> {
         self.parse_name_value_directive(line, "forbid-output")
     }
@@ -474,6 +954,11 @@ impl Config {
         self.parse_name_value_directive(line, "aux-build")
     }
 
+    fn parse_aux_host_build(&self, line: &str, directive: &str) -> Option<String> {
+        self.parse_name_value_directive(line, directive)
+            .map(|ab| ab.trim().to_string())
+    }
+
     fn parse_compile_flags(&self, line: &str) -> Option<String> {
         self.parse_name_value_directive(line, "compile-flags")
     }
@@ -483,6 +968,46 @@ impl Config {
             .map(|r| r.split_whitespace().map(|t| t.to_string()).collect())
     }
 
+    /// The combinations of flags a header like
+    /// `compile-flags-matrix: [-O | -C opt-level=0] x [--cfg a | --cfg b]`
+    /// stands for, each with a name to be used as its revision, like
+    /// `O__cfg_a`.
+    fn parse_flag_matrix(&self, line: &str) -> Option<Vec<(String, Vec<String>)>> {
+        let value = self.parse_name_value_directive(line, "compile-flags-matrix")?;
+        let mut combinations = vec![(String::new(), Vec::new())];
+        for group in value.split(" x ") {
+            let group = group.trim();
+            if !group.starts_with('[') || !group.ends_with(']') {
+                panic!(
+                    "malformed header: expected `compile-flags-matrix: [FLAGS | FLAGS] x ...`, \
+                     found `{}`",
+                    line
+                );
+            }
+            let alternatives = group[1..group.len() - 1].split('|').collect::<Vec<_>>();
+            let mut next = Vec::new();
+            for &(ref name, ref flags) in &combinations {
+                for alternative in &alternatives {
+                    let mut name = name.clone();
+                    if !name.is_empty() {
+                        name.push_str("__");
+                    }
+                    name.push_str(&flags_name(alternative));
+                    let mut flags = flags.clone();
+                    flags.extend(alternative.split_whitespace().map(str::to_string));
+                    next.push((name, flags));
+                }
+            }
+            combinations = next;
+        }
+        for (i, &(ref name, _)) in combinations.iter().enumerate() {
+            if combinations[..i].iter().any(|c| c.0 == *name) {
+                panic!("two combinations in `{}` are both named `{}`", line, name);
+            }
+        }
+        Some(combinations)
+    }
+
     fn parse_run_flags(&self, line: &str) -> Option<String> {
         self.parse_name_value_directive(line, "run-flags")
     }
@@ -499,8 +1024,17 @@ impl Config {
         self.parse_name_directive(line, "build-aux-docs")
     }
 
-    fn parse_check_stdout(&self, line: &str) -> bool {
-        self.parse_name_directive(line, "check-stdout")
+    fn parse_checked_output(&self, line: &str) -> Option<CheckedOutput> {
+        if self.parse_name_directive(line, "check-stdout") {
+            return Some(CheckedOutput::Both);
+        }
+        self.parse_name_value_directive(line, "check-output")
+            .map(|output| match output.trim() {
+                "stdout" => CheckedOutput::Stdout,
+                "stderr" => CheckedOutput::Stderr,
+                "both" => CheckedOutput::Both,
+                output => panic!("unknown check-output `{}`", output),
+            })
     }
 
     fn parse_no_prefer_dynamic(&self, line: &str) -> bool {
@@ -520,12 +1054,63 @@ impl Config {
     }
 
     fn parse_failure_status(&self, line: &str) -> Option<i32> {
-        match self.parse_name_value_directive(line, "failure-status") {
+        let status = self
+            .parse_name_value_directive(line, "failure-status")
+            .or_else(|| self.parse_name_value_directive(line, "exit-code"));
+        match status {
             Some(code) => code.trim().parse::<i32>().ok(),
             _ => None,
         }
     }
 
+    fn parse_timeout(&self, line: &str) -> Option<u64> {
+        match self.parse_name_value_directive(line, "timeout") {
+            Some(secs) => secs.trim().parse::<u64>().ok(),
+            _ => None,
+        }
+    }
+
+    fn parse_run_input(&self, line: &str) -> Option<String> {
+        self.parse_name_value_directive(line, "run-input")
+            .map(|file| file.trim().to_string())
+    }
+
+    fn parse_max_rss(&self, line: &str) -> Option<u64> {
+        self.parse_name_value_directive(line, "max-rss").map(|mib| {
+            mib.trim()
+                .parse::<u64>()
+                .unwrap_or_else(|_| panic!("`max-rss` must be a number of MiB, found `{}`", mib))
+        })
+    }
+
+    fn parse_max_binary_size(&self, line: &str) -> Option<u64> {
+        self.parse_name_value_directive(line, "max-binary-size").map(|bytes| {
+            bytes.trim().parse::<u64>().unwrap_or_else(|_| {
+                panic!("`max-binary-size` must be a number of bytes, found `{}`", bytes)
+            })
+        })
+    }
+
+    fn parse_assembly_output(&self, line: &str) -> Option<String> {
+        self.parse_name_value_directive(line, "assembly-output")
+            .map(|r| r.trim().to_string())
+    }
+
+    fn parse_known_bug(&self, line: &str, testfile: &Path) -> Option<String> {
+        self.parse_name_value_directive(line, "known-bug").map(|issue| {
+            let issue = issue.trim();
+            let number = issue.splitn(2, '#').nth(1).unwrap_or("");
+            if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+                panic!(
+                    "`known-bug` in {} must name an issue like `#12345`, but found `{}`",
+                    testfile.display(),
+                    issue
+                );
+            }
+            issue.to_string()
+        })
+    }
+
     fn parse_compile_pass(&self, line: &str) -> bool {
         self.parse_name_directive(line, "compile-pass")
     }
@@ -573,7 +1158,10 @@ impl Config {
     }
 
     fn parse_custom_normalization(&self, mut line: &str, prefix: &str) -> Option<(String, String)> {
-        if self.parse_cfg_name_directive(line, prefix) {
+        // `normalize-stderr: ...` applies everywhere, just like `normalize-stderr-test: ...`.
+        let unconditional =
+            line.starts_with(prefix) && line.as_bytes().get(prefix.len()) == Some(&b':');
+        if unconditional || self.parse_cfg_name_directive(line, prefix) {
             let from = match parse_normalization_string(&mut line) {
                 Some(s) => s,
                 None => return None,
@@ -601,7 +1189,7 @@ impl Config {
                 util::matches_os(&self.target, name) ||             // target
                 name == util::get_arch(&self.target) ||             // architecture
                 name == util::get_pointer_width(&self.target) ||    // pointer width
-                name == self.stage_id.split('-').next().unwrap() || // stage
+                name == self.stage() ||                             // stage
                 Some(name) == util::get_env(&self.target) ||        // env
                 match self.mode {
                     common::DebugInfoGdb => name == "gdb",
@@ -639,8 +1227,13 @@ impl Config {
         let colon = directive.len();
         if line.starts_with(directive) && line.as_bytes().get(colon) == Some(&b':') {
             let value = line[(colon + 1)..].to_owned();
-            debug!("{}: {}", directive, value);
+            trace!("{}: {}", directive, value);
             Some(expand_variables(value, self))
+        } else if line.starts_with(directive)
+            && line[colon..].starts_with(char::is_whitespace)
+            && line[colon..].trim_left().starts_with(|c| c == ':' || c == '=')
+        {
+            panic!("malformed header: expected `{}: VALUE`, found `{}`", directive, line)
         } else {
             None
         }
@@ -707,18 +1300,93 @@ fn expand_variables(mut value: String, config: &Config) -> String {
 /// let first = parse_normalization_string(&mut s);
 /// assert_eq!(first, Some("something (32 bits)".to_owned()));
 /// assert_eq!(s, " -> \"something ($WORD bits)\".");
+///
+/// let mut s = r#"normalize-stderr: "say \"hi\"" -> "greet""#;
+/// assert_eq!(parse_normalization_string(&mut s), Some(r#"say "hi""#.to_owned()));
 /// ```
 fn parse_normalization_string(line: &mut &str) -> Option<String> {
-    // FIXME support escapes in strings.
-    let begin = match line.find('"') {
+    let rest: &str = *line;
+    let begin = match rest.find('"') {
         Some(i) => i + 1,
         None => return None,
     };
-    let end = match line[begin..].find('"') {
-        Some(i) => i + begin,
-        None => return None,
-    };
-    let result = line[begin..end].to_owned();
-    *line = &line[end + 1..];
-    Some(result)
+    // `\"` stands for a quote within the string. Other backslashes are kept
+    // as they are, they're most likely part of a regex.
+    let mut result = String::new();
+    let mut chars = rest[begin..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                *line = &rest[begin + i + 1..];
+                return Some(result);
+            }
+            '\\' if rest[begin + i + 1..].starts_with('"') => {
+                chars.next();
+                result.push('"');
+            }
+            c => result.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub fn test_config() -> Config {
+    let args = &[
+        "compiletest",
+        "--compile-lib-path=",
+        "--run-lib-path=",
+        "--rustc-path=rustc",
+        "--lldb-python=",
+        "--docck-python=",
+        "--src-base=",
+        "--build-base=",
+        "--stage-id=stage2",
+        "--mode=ui",
+        "--cc=c",
+        "--cxx=c++",
+        "--cflags=",
+        "--llvm-components=",
+        "--llvm-cxxflags=",
+        "--android-cross-path=",
+    ];
+    ::parse_config(args.iter().map(|s| s.to_string()).collect())
+}
+
+#[test]
+fn test_parse_flag_matrix() {
+    let config = test_config();
+    let matrix = config
+        .parse_flag_matrix("compile-flags-matrix: [-O | -C opt-level=0] x [--cfg a | --cfg b]")
+        .unwrap();
+    let names: Vec<&str> = matrix.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(
+        names,
+        ["O__cfg_a", "O__cfg_b", "C_opt_level_0__cfg_a", "C_opt_level_0__cfg_b"]
+    );
+    assert_eq!(matrix[0].1, ["-O", "--cfg", "a"]);
+    assert_eq!(matrix[3].1, ["-C", "opt-level=0", "--cfg", "b"]);
+
+    assert!(config.parse_flag_matrix("compile-flags: -O").is_none());
+}
+
+#[test]
+fn test_flags_name() {
+    assert_eq!(flags_name("-C opt-level=0"), "C_opt_level_0");
+    assert_eq!(flags_name(" --cfg a "), "cfg_a");
+    assert_eq!(flags_name("-C opt-level=3 -C debuginfo=2"), "C_opt_level_3_C_debuginfo_2");
+    assert_eq!(flags_name(""), "none");
+    assert_eq!(flags_name("-1"), "_1");
+}
+
+#[test]
+#[should_panic(expected = "are both named `cfg_a`")]
+fn test_parse_flag_matrix_duplicate_name() {
+    test_config().parse_flag_matrix("compile-flags-matrix: [--cfg a | --cfg=a]");
+}
+
+#[test]
+#[should_panic(expected = "malformed header")]
+fn test_parse_flag_matrix_malformed_group() {
+    test_config().parse_flag_matrix("compile-flags-matrix: [-O | -C opt-level=0] x --cfg a");
 }
@@ -0,0 +1,150 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Making sure the processes run by tests don't outlive them.
+//!
+//! Killing just the process a test started leaves anything that process
+//! spawned itself running, like the program valgrind runs or the processes a
+//! test program forks. On Unix, every process started with `spawn` therefore
+//! gets a process group of its own, and `kill` takes down the whole group.
+//!
+//! Being in a group of their own, the processes no longer get the SIGINT sent
+//! to the foreground process group when compiletest is interrupted, so
+//! `kill_on_interrupt` installs a handler that kills the groups of all
+//! processes still running before compiletest itself goes down.
+//!
+//! Windows needs none of this: `kill` uses `taskkill /T`, which kills the whole
+//! tree, and bootstrap puts itself and everything it runs, compiletest
+//! included, into a job object that dies with it.
+
+use std::io;
+use std::process::Child;
+
+use command::Command;
+
+#[cfg(unix)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(unix)]
+use libc;
+
+/// How many process groups the interrupt handler keeps track of; more than
+/// there are tests running at the same time.
+#[cfg(unix)]
+const MAX_GROUPS: usize = 256;
+
+#[cfg(unix)]
+lazy_static! {
+    // The process groups of the processes currently running, with unused
+    // slots set to 0. A fixed set of atomics, as the interrupt handler can't
+    // take a lock.
+    static ref GROUPS: Vec<AtomicUsize> = (0..MAX_GROUPS).map(|_| AtomicUsize::new(0)).collect();
+}
+
+/// The process group of a process started with `spawn`, which the interrupt
+/// handler kills until this is dropped.
+pub struct Group {
+    slot: Option<usize>,
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            release(slot);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn release(slot: usize) {
+    GROUPS[slot].store(0, Ordering::SeqCst);
+}
+
+#[cfg(not(unix))]
+fn release(_slot: usize) {}
+
+/// Starts `command` in a process group of its own.
+#[cfg(unix)]
+pub fn spawn(command: &mut Command) -> io::Result<(Child, Group)> {
+    command.before_exec(|| {
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+        Ok(())
+    });
+    let child = command.spawn()?;
+    let pid = child.id() as usize;
+    let slot = GROUPS
+        .iter()
+        .position(|group| {
+            group
+                .compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+    Ok((child, Group { slot }))
+}
+
+#[cfg(not(unix))]
+pub fn spawn(command: &mut Command) -> io::Result<(Child, Group)> {
+    let child = command.spawn()?;
+    Ok((child, Group { slot: None }))
+}
+
+/// Kills the process `pid` started with `spawn` along with everything it
+/// spawned itself.
+#[cfg(unix)]
+pub fn kill(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+pub fn kill(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(&["/F", "/T", "/PID", &pid.to_string()])
+        .output();
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn kill(_pid: u32) {}
+
+/// Makes SIGINT, SIGTERM and SIGHUP kill the process groups of all processes
+/// started with `spawn` that are still running, before terminating
+/// compiletest as usual.
+#[cfg(unix)]
+pub fn kill_on_interrupt() {
+    extern "C" fn handler(signal: libc::c_int) {
+        for group in GROUPS.iter() {
+            let pid = group.load(Ordering::SeqCst);
+            if pid != 0 {
+                unsafe {
+                    libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+                }
+            }
+        }
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    // The handler must not be the one to initialize `GROUPS`.
+    ::lazy_static::initialize(&GROUPS);
+    for &signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe {
+            libc::signal(signal, handler as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill_on_interrupt() {}
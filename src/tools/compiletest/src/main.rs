@@ -23,7 +23,6 @@ extern crate libc;
 extern crate log;
 extern crate regex;
 #[macro_use]
-#[cfg(windows)]
 extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
@@ -31,45 +30,101 @@ extern crate serde_json;
 extern crate test;
 extern crate rustfix;
 
-use common::CompareMode;
+use common::{CompareMode, KeepArtifacts};
 use common::{expected_output_path, output_base_dir, output_relative_path, UI_EXTENSIONS};
-use common::{Config, TestPaths};
-use common::{DebugInfoGdb, DebugInfoLldb, Mode, Pretty};
+use common::{Config, TestPaths, DEFAULT_OUTPUT_LIMIT};
+use common::{CoverageMap, CoverageRun, DebugInfoGdb, DebugInfoLldb, Mode, Pretty};
 use filetime::FileTime;
 use getopts::Options;
+use std::cmp;
+use std::collections::HashSet;
 use std::env;
 use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use test::ColorConfig;
-use util::logv;
+use std::time::Instant;
+use test::{ColorConfig, OutputFormat};
 
 use self::header::EarlyProps;
 
+mod aux_cache;
+mod command;
 pub mod common;
+mod core_dump;
+mod coverage;
 pub mod errors;
+mod examples;
 pub mod header;
+mod job;
 mod json;
+mod order;
 mod raise_fd_limit;
 mod read2;
+mod record;
+pub mod report;
 pub mod runtest;
 pub mod util;
 
 fn main() {
-    env_logger::init();
-
     let config = parse_config(env::args().collect());
-
-    if config.valgrind_path.is_none() && config.force_valgrind {
-        panic!("Can't find Valgrind to run Valgrind tests");
-    }
-
+    util::init_logging(&config);
     log_config(&config);
     run_tests(&config);
 }
 
+/// Makes sure that the tools the tests of this suite need are there, so that
+/// a missing one is reported once instead of by every test. Only called when
+/// some test is going to run.
+fn check_tools(config: &Config) {
+    let mut missing = Vec::new();
+    {
+        let mut require = |path: Option<&Path>, what: &str, flag: &str| match path {
+            Some(path) if util::find_program(path).is_some() => {}
+            Some(path) => {
+                missing.push(format!("{} `{}` (from `--{}`)", what, path.display(), flag))
+            }
+            None => missing.push(format!("{} (pass `--{}`)", what, flag)),
+        };
+
+        if config.force_valgrind {
+            require(config.valgrind_path.as_ref().map(Path::new), "Valgrind", "valgrind-path");
+        }
+        match config.mode {
+            Mode::Codegen | Mode::Assembly => {
+                let filecheck = config.llvm_filecheck.as_ref().map(|p| &**p);
+                require(filecheck, "FileCheck", "llvm-filecheck");
+            }
+            Mode::CoverageRun if ignore_for_mode(config).is_none() => {
+                let llvm_profdata = config.llvm_tool("llvm-profdata");
+                require(llvm_profdata.as_ref().map(|p| &**p), "llvm-profdata", "llvm-bin-dir");
+            }
+            Mode::DebugInfoGdb
+                if !config.target.contains("android") && config.remote_test_client.is_none() =>
+            {
+                require(config.gdb.as_ref().map(Path::new), "GDB", "gdb");
+            }
+            Mode::DebugInfoLldb if config.lldb_version.is_some() => {
+                require(Some(Path::new(&config.lldb_python)), "Python for LLDB", "lldb-python");
+            }
+            Mode::Rustdoc => {
+                require(Some(Path::new(&config.docck_python)), "Python", "docck-python");
+            }
+            _ => {}
+        }
+        if config.adb_device_status {
+            require(Some(Path::new(&config.adb_path)), "adb", "adb-path");
+        }
+        if let Some(ref client) = config.remote_test_client {
+            require(Some(client), "remote-test-client", "remote-test-client");
+        }
+    }
+    if !missing.is_empty() {
+        panic!("can't find tools needed by {} tests:\n    {}", config.mode, missing.join("\n    "));
+    }
+}
+
 pub fn parse_config(args: Vec<String>) -> Config {
     let mut opts = Options::new();
     opts.reqopt(
@@ -95,6 +150,12 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "path to rustdoc to use for compiling",
             "PATH",
         )
+        .optopt(
+            "",
+            "reference-rustc",
+            "rustc whose pretty-printed source pretty tests have to match",
+            "PATH",
+        )
         .reqopt(
             "",
             "lldb-python",
@@ -118,12 +179,55 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "force-valgrind",
             "fail if Valgrind tests cannot be run under Valgrind",
         )
+        .optopt(
+            "",
+            "valgrind-error-code",
+            "exit code for Valgrind to report errors with (default: 100)",
+            "CODE",
+        )
+        .optopt(
+            "",
+            "sanitizer",
+            "build run-pass and run-fail tests with a sanitizer instead of running \
+             them under Valgrind: address, leak, memory or thread",
+            "SANITIZER",
+        )
+        .optflag(
+            "",
+            "deny-warnings",
+            "fail run-pass tests whose compilation emits warnings",
+        )
+        .optflag(
+            "",
+            "compile-fail-metadata",
+            "check compile-fail tests with --emit=metadata instead of building them",
+        )
+        .optflag(
+            "",
+            "compare-compile-fail-metadata",
+            "like --compile-fail-metadata, but also build the tests, fail those whose \
+             errors only show up when built, and report the time each way took",
+        )
         .optopt(
             "",
             "llvm-filecheck",
-            "path to LLVM's FileCheck binary",
+            "path to LLVM's FileCheck binary, found in PATH by default",
+            "DIR",
+        )
+        .optopt(
+            "",
+            "llvm-bin-dir",
+            "directory with LLVM's tools, like llvm-profdata for coverage and run-make \
+             tests, found in PATH by default",
             "DIR",
         )
+        .optopt(
+            "",
+            "nm",
+            "program listing the symbols of binaries for check-symbol headers, \
+             except on MSVC targets, where the linker lists them (default: nm)",
+            "PATH",
+        )
         .reqopt("", "src-base", "directory to scan for test files", "PATH")
         .reqopt(
             "",
@@ -145,7 +249,55 @@ pub fn parse_config(args: Vec<String>) -> Config {
              run-pass-valgrind|pretty|debug-info|incremental|mir-opt)",
         )
         .optflag("", "ignored", "run tests marked as ignored")
+        .optmulti(
+            "",
+            "skip",
+            "skip tests whose name contains FILTER (this flag can be used multiple times)",
+            "FILTER",
+        )
         .optflag("", "exact", "filters match exactly")
+        .optmulti(
+            "",
+            "force-header",
+            "treat every test as if it had this header, e.g. `compile-flags=-g`",
+            "KEY[=VALUE]",
+        )
+        .optmulti(
+            "",
+            "pass-env",
+            "let the processes run by tests inherit this environment variable",
+            "NAME",
+        )
+        .optmulti(
+            "",
+            "suppress-header",
+            "ignore this header, e.g. `ignore-windows`, in every test",
+            "KEY",
+        )
+        .optflag(
+            "",
+            "list",
+            "list the tests along with their mode, revisions and auxiliary \
+             files instead of running them",
+        )
+        .optflag("", "fail-fast", "stop the suite as soon as a test fails")
+        .optflag(
+            "",
+            "resume",
+            "only run the tests that failed in the previous run, if any did",
+        )
+        .optopt(
+            "",
+            "quarantine",
+            "run the tests listed in FILE, one per line, but don't let them fail the suite",
+            "FILE",
+        )
+        .optopt(
+            "",
+            "shard",
+            "only run the tests in shard K of N, split deterministically by test name",
+            "K/N",
+        )
         .optopt(
             "",
             "runtool",
@@ -153,6 +305,18 @@ pub fn parse_config(args: Vec<String>) -> Config {
              (eg. emulator, valgrind)",
             "PROGRAM",
         )
+        .optopt(
+            "",
+            "qemu",
+            "QEMU user-mode emulator to run compiled tests with (eg. qemu-arm)",
+            "PROGRAM",
+        )
+        .optopt(
+            "",
+            "qemu-sysroot",
+            "target sysroot for the QEMU emulator to load libraries from",
+            "PATH",
+        )
         .optopt(
             "",
             "host-rustcflags",
@@ -165,7 +329,90 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "flags to pass to rustc for target",
             "FLAGS",
         )
-        .optflag("", "verbose", "run tests verbosely, showing all output")
+        .optflag(
+            "",
+            "stream-output",
+            "print the output of every process line by line as it runs",
+        )
+        .optflagmulti(
+            "",
+            "verbose",
+            "run tests verbosely; pass twice to also show the output of every process",
+        )
+        .optopt(
+            "",
+            "log",
+            "which log messages to print, e.g. `debug` or `compiletest::runtest=trace` \
+             (defaults to COMPILETEST_LOG, or `info` with --verbose)",
+            "FILTER",
+        )
+        .optopt(
+            "j",
+            "jobs",
+            "number of tests to run concurrently (defaults to RUST_TEST_THREADS \
+             or the number of CPUs)",
+            "N",
+        )
+        .optopt(
+            "",
+            "timeout",
+            "kill processes spawned by a test after SECS seconds",
+            "SECS",
+        )
+        .optopt(
+            "",
+            "retry",
+            "rerun failed run-pass and run-fail tests up to N times",
+            "N",
+        )
+        .optflag(
+            "",
+            "check-dep-info",
+            "check that the dep-info of tests only lists existing files in the test tree",
+        )
+        .optopt(
+            "",
+            "bench-compile",
+            "compile each test N times instead of testing it, and write the compile times \
+             to compile-bench.csv",
+            "N",
+        )
+        .optopt(
+            "",
+            "output-limit",
+            &format!(
+                "keep at most BYTES of the stdout and stderr of each process (default {})",
+                DEFAULT_OUTPUT_LIMIT
+            ),
+            "BYTES",
+        )
+        .optflag(
+            "",
+            "spill-output",
+            "write the complete output of processes to the .out and .err files",
+        )
+        .optflag(
+            "",
+            "collect-core-dumps",
+            "keep the core dumps of crashing processes next to the output of their test",
+        )
+        .optopt(
+            "",
+            "debug-test",
+            "run only the test named NAME, print the commands it runs, keep everything it \
+             builds and write an env.sh for running its binary by hand",
+            "NAME",
+        )
+        .optflag(
+            "",
+            "debug-test-pause",
+            "with --debug-test, stop the test binary before it starts so a debugger can attach",
+        )
+        .optflag(
+            "",
+            "share-aux-builds",
+            "build each auxiliary crate once per run and reuse it in the tests that need it",
+        )
         .optflag(
             "",
             "bless",
@@ -176,8 +423,56 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "quiet",
             "print one character per test instead of one line",
         )
+        .optopt(
+            "",
+            "format",
+            "output format: pretty, terse, or json (one record per test)",
+            "FORMAT",
+        )
         .optopt("", "color", "coloring: auto, always, never", "WHEN")
         .optopt("", "logfile", "file to log test execution to", "FILE")
+        .optopt(
+            "",
+            "junit-report",
+            "write a JUnit XML report of the suite to PATH",
+            "PATH",
+        )
+        .optflag(
+            "",
+            "html-report",
+            "write an HTML report of the suite to report.html in the build directory",
+        )
+        .optopt("", "slowest", "print the N slowest tests at the end", "N")
+        .optopt(
+            "",
+            "timings-csv",
+            "write the compile and run times of each test to PATH as CSV",
+            "PATH",
+        )
+        .optopt(
+            "",
+            "binary-sizes-csv",
+            "write the size of the binary built by each test to PATH as CSV",
+            "PATH",
+        )
+        .optopt(
+            "",
+            "summary-json",
+            "merge a summary of the suite, keyed by mode, into the JSON file at PATH",
+            "PATH",
+        )
+        .optopt(
+            "",
+            "record-procs",
+            "record the processes tests run, with their output, to DIR",
+            "DIR",
+        )
+        .optopt(
+            "",
+            "replay-procs",
+            "replay the processes recorded in DIR instead of running them",
+            "DIR",
+        )
         .optopt("", "target", "the target to build for", "TARGET")
         .optopt("", "host", "the host to build for", "HOST")
         .optopt(
@@ -199,6 +494,11 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "VERSION STRING",
         )
         .optflag("", "system-llvm", "is LLVM the system LLVM")
+        .optflag(
+            "",
+            "rustc-debug-assertions",
+            "the compiler under test was built with debug assertions",
+        )
         .optopt(
             "",
             "android-cross-path",
@@ -231,6 +531,12 @@ pub fn parse_config(args: Vec<String>) -> Config {
         )
         .reqopt("", "llvm-cxxflags", "C++ flags for LLVM", "FLAGS")
         .optopt("", "nodejs", "the name of nodejs", "PATH")
+        .optopt(
+            "",
+            "wasm-runtime",
+            "the wasm runtime to run wasm32 tests with instead of nodejs (eg. wasmtime)",
+            "PATH",
+        )
         .optopt(
             "",
             "remote-test-client",
@@ -243,6 +549,13 @@ pub fn parse_config(args: Vec<String>) -> Config {
             "mode describing what file the actual ui output will be compared to",
             "COMPARE MODE",
         )
+        .optopt(
+            "",
+            "keep-artifacts",
+            "which tests keep their executables, output and scratch directories \
+             (default: failing)",
+            "all|failing|never",
+        )
         .optflag("h", "help", "show this message");
 
     let (argv0, args_) = args.split_first().unwrap();
@@ -292,19 +605,123 @@ pub fn parse_config(args: Vec<String>) -> Config {
         ),
     };
 
+    let format = match matches.opt_str("format").as_ref().map(|x| &**x) {
+        Some("pretty") => OutputFormat::Pretty,
+        Some("terse") => OutputFormat::Terse,
+        Some("json") => OutputFormat::Json,
+        None if matches.opt_present("quiet") => OutputFormat::Terse,
+        None => OutputFormat::Pretty,
+        Some(x) => panic!(
+            "argument for --format must be pretty, terse, or json, but found `{}`",
+            x
+        ),
+    };
+
+    let jobs = matches.opt_str("jobs").map(|j| match j.parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("argument for --jobs must be a positive number, but found `{}`", j),
+    });
+
+    let timeout = matches.opt_str("timeout").map(|t| match t.parse::<u64>() {
+        Ok(secs) if secs > 0 => secs,
+        _ => panic!("argument for --timeout must be a positive number, but found `{}`", t),
+    });
+
+    let retry = matches.opt_str("retry").map_or(0, |n| match n.parse::<usize>() {
+        Ok(n) => n,
+        _ => panic!("argument for --retry must be a number, but found `{}`", n),
+    });
+
+    let debug_test = matches.opt_str("debug-test");
+    let debugging = debug_test.is_some();
+    if matches.opt_present("debug-test-pause") && !debugging {
+        panic!("--debug-test-pause requires --debug-test");
+    }
+
+    let nm = matches.opt_str("nm").unwrap_or_else(|| "nm".to_string());
+
+    let bench_compile = matches.opt_str("bench-compile").map(|n| match n.parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("argument for --bench-compile must be a positive number, but found `{}`", n),
+    });
+
+    let output_limit =
+        matches.opt_str("output-limit").map_or(DEFAULT_OUTPUT_LIMIT, |n| match n.parse() {
+            Ok(n) => n,
+            _ => panic!("argument for --output-limit must be a number, but found `{}`", n),
+        });
+
+    let shard = matches.opt_str("shard").map(|s| {
+        let mut parts = s.splitn(2, '/').map(|p| p.trim().parse::<u64>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(k)), Some(Ok(n))) if 1 <= k && k <= n => (k, n),
+            _ => panic!(
+                "argument for --shard must be of the form K/N with 1 <= K <= N, but found `{}`",
+                s
+            ),
+        }
+    });
+
+    let slowest = matches.opt_str("slowest").map(|n| match n.parse::<usize>() {
+        Ok(n) => n,
+        _ => panic!("argument for --slowest must be a number, but found `{}`", n),
+    });
+
+    let valgrind_error_code = matches.opt_str("valgrind-error-code").map_or(100, |c| {
+        match c.parse::<i32>() {
+            Ok(c) if c > 0 => c,
+            _ => panic!(
+                "argument for --valgrind-error-code must be a positive number, but found `{}`",
+                c
+            ),
+        }
+    });
+
+    let sanitizer = match matches.opt_str("sanitizer").as_ref().map(|x| &**x) {
+        Some(s @ "address") | Some(s @ "leak") | Some(s @ "memory") | Some(s @ "thread") => {
+            Some(s.to_string())
+        }
+        None => None,
+        Some(x) => panic!(
+            "argument for --sanitizer must be address, leak, memory or thread, but found `{}`",
+            x
+        ),
+    };
+
+    let rustc_path = opt_path(matches, "rustc-path");
+    let llvm_version = analyze_llvm(matches.opt_str("llvm-version"), &rustc_path);
+    let target_rustcflags = matches.opt_str("target-rustcflags");
+    let debug_assertions = matches.opt_present("rustc-debug-assertions")
+        || rustc_cfg(&rustc_path, &opt_str2(matches.opt_str("target")), &target_rustcflags)
+            .iter()
+            .any(|cfg| cfg == "debug_assertions");
+    let optimize_tests = is_optimized(&target_rustcflags);
+
     let src_base = opt_path(matches, "src-base");
     let run_ignored = matches.opt_present("ignored");
     Config {
         bless: matches.opt_present("bless"),
         compile_lib_path: make_absolute(opt_path(matches, "compile-lib-path")),
         run_lib_path: make_absolute(opt_path(matches, "run-lib-path")),
-        rustc_path: opt_path(matches, "rustc-path"),
+        rustc_path,
         rustdoc_path: matches.opt_str("rustdoc-path").map(PathBuf::from),
+        reference_rustc: matches.opt_str("reference-rustc").map(PathBuf::from),
         lldb_python: matches.opt_str("lldb-python").unwrap(),
         docck_python: matches.opt_str("docck-python").unwrap(),
         valgrind_path: matches.opt_str("valgrind-path"),
         force_valgrind: matches.opt_present("force-valgrind"),
-        llvm_filecheck: matches.opt_str("llvm-filecheck").map(|s| PathBuf::from(&s)),
+        valgrind_error_code,
+        sanitizer,
+        deny_warnings: matches.opt_present("deny-warnings"),
+        compile_fail_metadata: matches.opt_present("compile-fail-metadata")
+            || matches.opt_present("compare-compile-fail-metadata"),
+        compare_compile_fail_metadata: matches.opt_present("compare-compile-fail-metadata"),
+        llvm_filecheck: matches
+            .opt_str("llvm-filecheck")
+            .map(PathBuf::from)
+            .or_else(|| util::find_program(Path::new("FileCheck"))),
+        llvm_bin_dir: matches.opt_str("llvm-bin-dir").map(PathBuf::from),
+        nm,
         src_base,
         build_base: opt_path(matches, "build-base"),
         stage_id: matches.opt_str("stage-id").unwrap(),
@@ -314,19 +731,44 @@ pub fn parse_config(args: Vec<String>) -> Config {
             .parse()
             .expect("invalid mode"),
         run_ignored,
-        filter: matches.free.first().cloned(),
+        filter: debug_test.clone().or_else(|| matches.free.first().cloned()),
+        skip: matches.opt_strs("skip"),
         filter_exact: matches.opt_present("exact"),
+        list: matches.opt_present("list"),
+        forced_headers: matches
+            .opt_strs("force-header")
+            .iter()
+            .map(|h| h.replacen('=', ": ", 1))
+            .collect(),
+        suppressed_headers: matches.opt_strs("suppress-header"),
+        pass_env: matches.opt_strs("pass-env"),
+        fail_fast: matches.opt_present("fail-fast"),
+        resume: matches.opt_present("resume"),
+        quarantine: matches.opt_str("quarantine").map(PathBuf::from),
+        shard,
         logfile: matches.opt_str("logfile").map(|s| PathBuf::from(&s)),
+        junit_report: matches.opt_str("junit-report").map(PathBuf::from),
+        html_report: matches.opt_present("html-report"),
+        slowest,
+        timings_csv: matches.opt_str("timings-csv").map(PathBuf::from),
+        binary_sizes_csv: matches.opt_str("binary-sizes-csv").map(PathBuf::from),
+        summary_json: matches.opt_str("summary-json").map(PathBuf::from),
+        record_procs: matches.opt_str("record-procs").map(PathBuf::from),
+        replay_procs: matches.opt_str("replay-procs").map(PathBuf::from),
         runtool: matches.opt_str("runtool"),
+        qemu: matches.opt_str("qemu"),
+        qemu_sysroot: matches.opt_str("qemu-sysroot").map(PathBuf::from),
         host_rustcflags: matches.opt_str("host-rustcflags"),
-        target_rustcflags: matches.opt_str("target-rustcflags"),
+        target_rustcflags,
         target: opt_str2(matches.opt_str("target")),
         host: opt_str2(matches.opt_str("host")),
         gdb,
         gdb_version,
         gdb_native_rust,
         lldb_version: extract_lldb_version(matches.opt_str("lldb-version")),
-        llvm_version: matches.opt_str("llvm-version"),
+        llvm_version,
+        debug_assertions,
+        optimize_tests,
         system_llvm: matches.opt_present("system-llvm"),
         android_cross_path: opt_path(matches, "android-cross-path"),
         adb_path: opt_str2(matches.opt_str("adb-path")),
@@ -335,11 +777,32 @@ pub fn parse_config(args: Vec<String>) -> Config {
             && "(none)" != opt_str2(matches.opt_str("adb-test-dir"))
             && !opt_str2(matches.opt_str("adb-test-dir")).is_empty(),
         lldb_python_dir: matches.opt_str("lldb-python-dir"),
-        verbose: matches.opt_present("verbose"),
-        quiet: matches.opt_present("quiet"),
+        stream_output: matches.opt_present("stream-output"),
+        verbosity: matches.opt_count("verbose"),
+        log: matches.opt_str("log").or(env::var("COMPILETEST_LOG").ok()),
+        jobs,
+        // A test stopped in a debugger mustn't be killed for taking too long.
+        timeout: if debugging { None } else { timeout },
+        retry,
+        bench_compile,
+        check_dep_info: matches.opt_present("check-dep-info"),
+        output_limit,
+        spill_output: matches.opt_present("spill-output"),
+        collect_core_dumps: matches.opt_present("collect-core-dumps"),
+        debug_test,
+        debug_test_pause: matches.opt_present("debug-test-pause"),
+        share_aux_builds: matches.opt_present("share-aux-builds"),
+        format,
         color,
         remote_test_client: matches.opt_str("remote-test-client").map(PathBuf::from),
         compare_mode: matches.opt_str("compare-mode").map(CompareMode::parse),
+        keep_artifacts: if debugging {
+            KeepArtifacts::All
+        } else {
+            matches
+                .opt_str("keep-artifacts")
+                .map_or(KeepArtifacts::Failing, KeepArtifacts::parse)
+        },
 
         cc: matches.opt_str("cc").unwrap(),
         cxx: matches.opt_str("cxx").unwrap(),
@@ -349,61 +812,76 @@ pub fn parse_config(args: Vec<String>) -> Config {
         llvm_components: matches.opt_str("llvm-components").unwrap(),
         llvm_cxxflags: matches.opt_str("llvm-cxxflags").unwrap(),
         nodejs: matches.opt_str("nodejs"),
+        wasm_runtime: matches.opt_str("wasm-runtime"),
     }
 }
 
 pub fn log_config(config: &Config) {
-    let c = config;
-    logv(c, "configuration:".to_string());
-    logv(
-        c,
-        format!("compile_lib_path: {:?}", config.compile_lib_path),
+    info!("configuration:");
+    info!("compile_lib_path: {:?}", config.compile_lib_path);
+    info!("run_lib_path: {:?}", config.run_lib_path);
+    info!("rustc_path: {:?}", config.rustc_path.display());
+    info!("rustdoc_path: {:?}", config.rustdoc_path);
+    info!("nm: {}", config.nm);
+    info!("reference_rustc: {:?}", config.reference_rustc);
+    info!("src_base: {:?}", config.src_base.display());
+    info!("build_base: {:?}", config.build_base.display());
+    info!("stage_id: {}", config.stage_id);
+    info!("mode: {}", config.mode);
+    info!("run_ignored: {}", config.run_ignored);
+    info!(
+        "filter: {}",
+        opt_str(&config.filter.as_ref().map(|re| re.to_owned()))
     );
-    logv(c, format!("run_lib_path: {:?}", config.run_lib_path));
-    logv(c, format!("rustc_path: {:?}", config.rustc_path.display()));
-    logv(c, format!("rustdoc_path: {:?}", config.rustdoc_path));
-    logv(c, format!("src_base: {:?}", config.src_base.display()));
-    logv(c, format!("build_base: {:?}", config.build_base.display()));
-    logv(c, format!("stage_id: {}", config.stage_id));
-    logv(c, format!("mode: {}", config.mode));
-    logv(c, format!("run_ignored: {}", config.run_ignored));
-    logv(
-        c,
-        format!(
-            "filter: {}",
-            opt_str(&config.filter.as_ref().map(|re| re.to_owned()))
-        ),
-    );
-    logv(c, format!("filter_exact: {}", config.filter_exact));
-    logv(c, format!("runtool: {}", opt_str(&config.runtool)));
-    logv(
-        c,
-        format!("host-rustcflags: {}", opt_str(&config.host_rustcflags)),
-    );
-    logv(
-        c,
-        format!("target-rustcflags: {}", opt_str(&config.target_rustcflags)),
-    );
-    logv(c, format!("target: {}", config.target));
-    logv(c, format!("host: {}", config.host));
-    logv(
-        c,
-        format!(
-            "android-cross-path: {:?}",
-            config.android_cross_path.display()
-        ),
+    info!("skip: {:?}", config.skip);
+    info!("filter_exact: {}", config.filter_exact);
+    info!("list: {}", config.list);
+    info!("forced_headers: {:?}", config.forced_headers);
+    info!("suppressed_headers: {:?}", config.suppressed_headers);
+    info!("pass_env: {:?}", config.pass_env);
+    info!("fail_fast: {}", config.fail_fast);
+    info!("quarantine: {:?}", config.quarantine);
+    info!("resume: {}", config.resume);
+    info!("shard: {:?}", config.shard);
+    info!("runtool: {}", opt_str(&config.runtool));
+    info!("sanitizer: {}", opt_str(&config.sanitizer));
+    info!("debug_assertions: {}", config.debug_assertions);
+    info!("optimize_tests: {}", config.optimize_tests);
+    info!("deny_warnings: {}", config.deny_warnings);
+    info!("compile_fail_metadata: {}", config.compile_fail_metadata);
+    info!("compare_compile_fail_metadata: {}", config.compare_compile_fail_metadata);
+    info!("qemu: {}", opt_str(&config.qemu));
+    info!("qemu_sysroot: {:?}", config.qemu_sysroot);
+    info!("host-rustcflags: {}", opt_str(&config.host_rustcflags));
+    info!("target-rustcflags: {}", opt_str(&config.target_rustcflags));
+    info!("target: {}", config.target);
+    info!("host: {}", config.host);
+    info!(
+        "android-cross-path: {:?}",
+        config.android_cross_path.display()
     );
-    logv(c, format!("adb_path: {:?}", config.adb_path));
-    logv(c, format!("adb_test_dir: {:?}", config.adb_test_dir));
-    logv(
-        c,
-        format!("adb_device_status: {}", config.adb_device_status),
-    );
-    logv(c, format!("ar: {}", config.ar));
-    logv(c, format!("linker: {:?}", config.linker));
-    logv(c, format!("verbose: {}", config.verbose));
-    logv(c, format!("quiet: {}", config.quiet));
-    logv(c, "\n".to_string());
+    info!("adb_path: {:?}", config.adb_path);
+    info!("adb_test_dir: {:?}", config.adb_test_dir);
+    info!("adb_device_status: {}", config.adb_device_status);
+    info!("ar: {}", config.ar);
+    info!("linker: {:?}", config.linker);
+    info!("verbosity: {}", config.verbosity);
+    info!("log: {}", opt_str(&config.log));
+    info!("jobs: {:?}", config.jobs);
+    info!("timeout: {:?}", config.timeout);
+    info!("retry: {}", config.retry);
+    info!("bench_compile: {:?}", config.bench_compile);
+    info!("check_dep_info: {}", config.check_dep_info);
+    info!("output_limit: {}", config.output_limit);
+    info!("spill_output: {}", config.spill_output);
+    info!("collect_core_dumps: {}", config.collect_core_dumps);
+    info!("debug_test: {:?}", config.debug_test);
+    info!("debug_test_pause: {}", config.debug_test_pause);
+    info!("share_aux_builds: {}", config.share_aux_builds);
+    info!("format: {:?}", config.format);
+    info!("keep_artifacts: {:?}", config.keep_artifacts);
+    info!("record_procs: {:?}", config.record_procs);
+    info!("replay_procs: {:?}", config.replay_procs);
 }
 
 pub fn opt_str(maybestr: &Option<String>) -> &str {
@@ -421,6 +899,11 @@ pub fn opt_str2(maybestr: Option<String>) -> String {
 }
 
 pub fn run_tests(config: &Config) {
+    if config.list {
+        list_tests(config);
+        return;
+    }
+
     if config.target.contains("android") {
         if let DebugInfoGdb = config.mode {
             println!(
@@ -477,8 +960,35 @@ pub fn run_tests(config: &Config) {
         let _ = fs::remove_dir_all("tmp/partitioning-tests");
     }
 
-    let opts = test_opts(config);
+    let mut opts = test_opts(config);
+    // The debuggers set up above can't run tests in parallel, which has to
+    // take precedence over `--jobs`.
+    if env::var("RUST_TEST_THREADS").ok().map_or(false, |n| n == "1") {
+        opts.test_threads = Some(1);
+    }
     let tests = make_tests(config);
+    if let Some(ref name) = config.debug_test {
+        let matching: Vec<_> = tests
+            .iter()
+            .filter(|t| t.desc.ignore == opts.run_ignored)
+            .map(|t| t.desc.name.to_string())
+            .filter(|t| t.contains(&name[..]))
+            .collect();
+        if matching.len() != 1 {
+            panic!(
+                "--debug-test {} has to name exactly one test, but matches {}:\n{}",
+                name,
+                matching.len(),
+                matching.join("\n")
+            );
+        }
+    }
+    let total = count_matching(&opts, &tests, opts.run_ignored);
+    if total > 0 {
+        check_tools(config);
+    }
+    report::set_total(total);
+    report::set_ignored(count_matching(&opts, &tests, !opts.run_ignored));
     // sadly osx needs some file descriptor limits raised for running tests in
     // parallel (especially when we have lots and lots of child processes).
     // For context, see #8904
@@ -492,7 +1002,60 @@ pub fn run_tests(config: &Config) {
     // Let tests know which target they're running as
     env::set_var("TARGET", &config.target);
 
+    // Don't leave the processes of running tests behind when interrupted
+    job::kill_on_interrupt();
+
+    let start = Instant::now();
     let res = test::run_tests_console(&opts, tests.into_iter().collect());
+    let elapsed = start.elapsed();
+    if config.share_aux_builds {
+        aux_cache::clear(config);
+    }
+    report::print_flaky(config);
+    report::print_core_dumps(config);
+    if config.format != OutputFormat::Json {
+        report::print_summary(config, elapsed);
+    }
+    if let Some(ref path) = config.summary_json {
+        if let Err(e) = report::write_summary_json(config, elapsed, path) {
+            println!("failed to write suite summary to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(n) = config.slowest {
+        report::print_slowest(n);
+    }
+    if let Some(ref path) = config.timings_csv {
+        if let Err(e) = report::write_timings_csv(path) {
+            println!("failed to write test timings to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(ref path) = config.binary_sizes_csv {
+        if let Err(e) = report::write_binary_sizes_csv(path) {
+            println!("failed to write binary sizes to {}: {}", path.display(), e);
+        }
+    }
+    if config.bench_compile.is_some() {
+        let path = config.build_base.join("compile-bench.csv");
+        match report::write_compile_bench(&path) {
+            Ok(()) => println!("compile times written to {}", path.display()),
+            Err(e) => println!("failed to write compile times to {}: {}", path.display(), e),
+        }
+    }
+    if let Err(e) = report::write_last_failed(config) {
+        println!("failed to record the failed tests: {}", e);
+    }
+    if let Some(ref path) = config.junit_report {
+        if let Err(e) = report::write_junit(config, path) {
+            println!("failed to write JUnit report to {}: {}", path.display(), e);
+        }
+    }
+    if config.html_report {
+        let path = config.build_base.join("report.html");
+        match report::write_html(config, &path) {
+            Ok(()) => println!("HTML report written to {}", path.display()),
+            Err(e) => println!("failed to write HTML report to {}: {}", path.display(), e),
+        }
+    }
     match res {
         Ok(true) => {}
         Ok(false) => panic!("Some tests failed"),
@@ -502,26 +1065,43 @@ pub fn run_tests(config: &Config) {
     }
 }
 
+/// How many of `tests` pass the filters and are marked ignored or not, as
+/// given by `ignore`. With `ignore == opts.run_ignored`, this is the number of
+/// tests libtest is going to run.
+fn count_matching(opts: &test::TestOpts, tests: &[test::TestDescAndFn], ignore: bool) -> usize {
+    tests
+        .iter()
+        .filter(|t| t.desc.ignore == ignore)
+        .filter(|t| {
+            let name = t.desc.name.to_string();
+            let matches = |f: &String| {
+                if opts.filter_exact {
+                    name == *f
+                } else {
+                    name.contains(&f[..])
+                }
+            };
+            opts.filter.as_ref().map_or(true, &matches) && !opts.skip.iter().any(&matches)
+        })
+        .count()
+}
+
 pub fn test_opts(config: &Config) -> test::TestOpts {
     test::TestOpts {
         filter: config.filter.clone(),
         filter_exact: config.filter_exact,
         run_ignored: config.run_ignored,
-        format: if config.quiet {
-            test::OutputFormat::Terse
-        } else {
-            test::OutputFormat::Pretty
-        },
+        format: config.format,
         logfile: config.logfile.clone(),
         run_tests: true,
         bench_benchmarks: true,
-        nocapture: match env::var("RUST_TEST_NOCAPTURE") {
+        nocapture: config.debug_test.is_some() || match env::var("RUST_TEST_NOCAPTURE") {
             Ok(val) => &val != "0",
             Err(_) => false,
         },
         color: config.color,
-        test_threads: None,
-        skip: vec![],
+        test_threads: config.jobs,
+        skip: config.skip.clone(),
         list: false,
         options: test::Options::new(),
     }
@@ -530,14 +1110,85 @@ pub fn test_opts(config: &Config) -> test::TestOpts {
 pub fn make_tests(config: &Config) -> Vec<test::TestDescAndFn> {
     debug!("making tests from {:?}", config.src_base.display());
     let mut tests = Vec::new();
+    for paths in collect_tests(config) {
+        let early_props = early_props(config, &paths);
+        tests.push(order::Test {
+            entries: make_test(config, &paths, &early_props),
+            after: early_props.after,
+            paths,
+        });
+    }
+    let mut tests = order::order(config, tests);
+    if config.resume {
+        let last_failed = report::load_last_failed(config);
+        if !last_failed.is_empty() {
+            tests.retain(|t| last_failed.contains(&t.desc.name.to_string()));
+        }
+    }
+    if let Some((k, n)) = config.shard {
+        tests.retain(|t| shard_of(&t.desc.name.to_string(), n) == k);
+    }
+    if let Some(ref path) = config.quarantine {
+        let entries = load_quarantine(path);
+        let mut quarantined = HashSet::new();
+        for test in &mut tests {
+            let name = test.desc.name.to_string();
+            if entries.iter().any(|entry| is_quarantined(entry, &name)) {
+                // libtest reports tests that are allowed to fail separately,
+                // without failing the suite.
+                test.desc.allow_fail = true;
+                quarantined.insert(name);
+            }
+        }
+        report::set_quarantined(quarantined);
+    }
+    tests
+}
+
+/// The entries of the quarantine list in `path`: one test per line, with
+/// blank lines and lines starting with `#` left out.
+fn load_quarantine(path: &Path) -> Vec<String> {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("couldn't read quarantine list {}: {}", path.display(), e));
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether the quarantine list `entry` names the test `name`. An entry is
+/// either the whole name, like `[ui] ui/foo.rs#bar`, or the path in it, like
+/// `ui/foo.rs`, which names every revision of the test in any mode.
+fn is_quarantined(entry: &str, name: &str) -> bool {
+    let path = name.splitn(2, "] ").nth(1).unwrap_or(name);
+    entry == name || entry == path || path.starts_with(&format!("{}#", entry))
+}
+
+/// The shard (counting from 1) out of `n` a test with the given name falls in.
+/// This must not depend on anything but the name, so that separate runs of
+/// compiletest agree on how the tests are split up.
+fn shard_of(name: &str, n: u64) -> u64 {
+    // 64-bit FNV-1a, as `DefaultHasher` isn't guaranteed to be stable.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in name.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % n + 1
+}
+
+fn collect_tests(config: &Config) -> Vec<TestPaths> {
+    let mut found = Vec::new();
     collect_tests_from_dir(
         config,
         &config.src_base,
         &config.src_base,
         &PathBuf::new(),
-        &mut tests,
+        &mut found,
     ).unwrap();
-    tests
+    found
 }
 
 fn collect_tests_from_dir(
@@ -545,7 +1196,7 @@ fn collect_tests_from_dir(
     base: &Path,
     dir: &Path,
     relative_dir_path: &Path,
-    tests: &mut Vec<test::TestDescAndFn>,
+    found: &mut Vec<TestPaths>,
 ) -> io::Result<()> {
     // Ignore directories that contain a file
     // `compiletest-ignore-dir`.
@@ -560,7 +1211,7 @@ fn collect_tests_from_dir(
                 file: dir.to_path_buf(),
                 relative_dir: relative_dir_path.parent().unwrap().to_path_buf(),
             };
-            tests.extend(make_test(config, &paths));
+            found.push(paths);
             return Ok(());
         }
     }
@@ -581,18 +1232,18 @@ fn collect_tests_from_dir(
         let file = file?;
         let file_path = file.path();
         let file_name = file.file_name();
-        if is_test(&file_name) {
+        if is_test(config, &file_name) {
             debug!("found test file: {:?}", file_path.display());
             let paths = TestPaths {
                 file: file_path,
                 relative_dir: relative_dir_path.to_path_buf(),
             };
-            tests.extend(make_test(config, &paths))
+            found.push(paths);
         } else if file_path.is_dir() {
             let relative_file_path = relative_dir_path.join(file.file_name());
             if &file_name != "auxiliary" {
                 debug!("found directory: {:?}", file_path.display());
-                collect_tests_from_dir(config, base, &file_path, &relative_file_path, tests)?;
+                collect_tests_from_dir(config, base, &file_path, &relative_file_path, found)?;
             }
         } else {
             debug!("found other file/directory: {:?}", file_path.display());
@@ -601,10 +1252,11 @@ fn collect_tests_from_dir(
     Ok(())
 }
 
-pub fn is_test(file_name: &OsString) -> bool {
+pub fn is_test(config: &Config, file_name: &OsString) -> bool {
     let file_name = file_name.to_str().unwrap();
 
-    if !file_name.ends_with(".rs") {
+    let extension = if config.mode == Mode::DocExamples { ".md" } else { ".rs" };
+    if !file_name.ends_with(extension) {
         return false;
     }
 
@@ -613,14 +1265,92 @@ pub fn is_test(file_name: &OsString) -> bool {
     !invalid_prefixes.iter().any(|p| file_name.starts_with(p))
 }
 
-pub fn make_test(config: &Config, testpaths: &TestPaths) -> Vec<test::TestDescAndFn> {
-    let early_props = if config.mode == Mode::RunMake {
+fn early_props(config: &Config, testpaths: &TestPaths) -> EarlyProps {
+    if config.mode == Mode::RunMake {
         // Allow `ignore` directives to be in the Makefile.
         EarlyProps::from_file(config, &testpaths.file.join("Makefile"))
     } else {
         EarlyProps::from_file(config, &testpaths.file)
-    };
+    }
+}
 
+/// Whether the test can't be run no matter what its headers say, and why.
+fn ignore_for_mode(config: &Config) -> Option<&'static str> {
+    // Debugging emscripten code doesn't make sense today
+    if (config.mode == DebugInfoGdb || config.mode == DebugInfoLldb)
+        && config.target.contains("emscripten")
+    {
+        Some("debugging emscripten code is not supported")
+    } else if (config.mode == CoverageMap || config.mode == CoverageRun)
+        && env::var("PROFILER_SUPPORT").ok().map_or(true, |s| s != "1")
+    {
+        Some("coverage tests need the profiler runtime")
+    } else if config.mode == CoverageRun && config.remote_test_client.is_some() {
+        Some("coverage counts are written where the test runs")
+    } else if config.bench_compile.is_some()
+        && (config.mode == Mode::RunMake || config.mode == Mode::DocExamples)
+    {
+        Some("only single-file tests can be benchmarked")
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct Listing<'a> {
+    name: String,
+    mode: String,
+    ignore: Option<&'a str>,
+    revisions: &'a [String],
+    aux: &'a [String],
+}
+
+/// Print every test along with what its headers say about it, without
+/// building or running anything.
+fn list_tests(config: &Config) {
+    for paths in collect_tests(config) {
+        let name = make_test_name(config, &paths, None).to_string();
+        let matches = |filter: &String| {
+            if config.filter_exact {
+                name == *filter
+            } else {
+                name.contains(&filter[..])
+            }
+        };
+        if !config.filter.as_ref().map_or(true, &matches) || config.skip.iter().any(&matches) {
+            continue;
+        }
+
+        let props = early_props(config, &paths);
+        let listing = Listing {
+            name: name.clone(),
+            mode: config.mode.to_string(),
+            ignore: ignore_for_mode(config).or(props.ignore_reason.as_ref().map(|s| &s[..])),
+            revisions: &props.revisions,
+            aux: &props.aux,
+        };
+        if config.format == OutputFormat::Json {
+            println!("{}", serde_json::to_string(&listing).unwrap());
+            continue;
+        }
+        println!("{}", listing.name);
+        if let Some(reason) = listing.ignore {
+            println!("    ignored: {}", reason);
+        }
+        if !listing.revisions.is_empty() {
+            println!("    revisions: {}", listing.revisions.join(", "));
+        }
+        if !listing.aux.is_empty() {
+            println!("    aux: {}", listing.aux.join(", "));
+        }
+    }
+}
+
+pub fn make_test(
+    config: &Config,
+    testpaths: &TestPaths,
+    early_props: &EarlyProps,
+) -> Vec<(test::TestDesc, order::Runner)> {
     // The `should-fail` annotation doesn't apply to pretty tests,
     // since we run the pretty printer across all tests by default.
     // If desired, we could add a `should-fail-pretty` annotation.
@@ -633,6 +1363,15 @@ pub fn make_test(config: &Config, testpaths: &TestPaths) -> Vec<test::TestDescAn
         },
     };
 
+    // Only tests running the compiled program are prone to spurious failures,
+    // unless the test says otherwise.
+    let retries = match config.mode {
+        _ if should_panic != test::ShouldPanic::No => 0,
+        _ if early_props.flaky => cmp::max(config.retry, 1),
+        Mode::RunPass | Mode::RunFail | Mode::RunPassValgrind => config.retry,
+        _ => 0,
+    };
+
     // Incremental tests are special, they inherently cannot be run in parallel.
     // `runtest::run` will be responsible for iterating over revisions.
     let revisions = if early_props.revisions.is_empty() || config.mode == Mode::Incremental {
@@ -643,25 +1382,24 @@ pub fn make_test(config: &Config, testpaths: &TestPaths) -> Vec<test::TestDescAn
     revisions
         .into_iter()
         .map(|revision| {
-            // Debugging emscripten code doesn't make sense today
-            let ignore = early_props.ignore
-                || !up_to_date(
-                    config,
-                    testpaths,
-                    &early_props,
-                    revision.map(|s| s.as_str()),
-                )
-                || (config.mode == DebugInfoGdb || config.mode == DebugInfoLldb)
-                    && config.target.contains("emscripten");
-            test::TestDescAndFn {
-                desc: test::TestDesc {
-                    name: make_test_name(config, testpaths, revision),
-                    ignore,
-                    should_panic,
-                    allow_fail: false,
-                },
-                testfn: make_test_closure(config, testpaths, revision),
-            }
+            let ignore = early_props.ignore || ignore_for_mode(config).is_some();
+            // Tests that passed before and whose inputs didn't change since
+            // aren't run again, but still reported as passing.
+            let cached =
+                !up_to_date(config, testpaths, early_props, revision.map(|s| s.as_str()));
+            let name = make_test_name(config, testpaths, revision);
+            let runner = if cached {
+                make_cached_closure(config, &name)
+            } else {
+                make_test_closure(config, testpaths, revision, &name, should_panic, retries)
+            };
+            let desc = test::TestDesc {
+                name,
+                ignore,
+                should_panic,
+                allow_fail: false,
+            };
+            (desc, runner)
         })
         .collect()
 }
@@ -676,6 +1414,15 @@ fn up_to_date(
     props: &EarlyProps,
     revision: Option<&str>,
 ) -> bool {
+    // Recording, replaying or benchmarking a test means running it, even if
+    // it passed before.
+    if config.record_procs.is_some()
+        || config.replay_procs.is_some()
+        || config.bench_compile.is_some()
+    {
+        return true;
+    }
+
     let stamp_name = stamp(config, testpaths, revision);
     // Check hash.
     let mut f = match fs::File::open(&stamp_name) {
@@ -685,7 +1432,7 @@ fn up_to_date(
     let mut contents = String::new();
     f.read_to_string(&mut contents)
         .expect("Can't read stamp contents");
-    let expected_hash = runtest::compute_stamp_hash(config);
+    let expected_hash = runtest::compute_stamp_hash(config, testpaths);
     if contents != expected_hash {
         return true;
     }
@@ -695,7 +1442,12 @@ fn up_to_date(
         .find_rust_src_root()
         .expect("Could not find Rust source root");
     let stamp = mtime(&stamp_name);
-    let mut inputs = vec![mtime(&testpaths.file), mtime(&config.rustc_path)];
+    let mut inputs = vec![mtime(&config.rustc_path)];
+    // The contents of test files are part of the hash, but run-make tests are
+    // directories.
+    if !testpaths.file.is_file() {
+        inputs.push(mtime(&testpaths.file));
+    }
     for aux in props.aux.iter() {
         inputs.push(mtime(&testpaths
             .file
@@ -704,6 +1456,12 @@ fn up_to_date(
             .join("auxiliary")
             .join(aux)));
     }
+    if let Some(ref input) = props.run_input {
+        inputs.push(mtime(&testpaths.file.parent().unwrap().join(input)));
+    }
+    for file in header::dir_header_files(config, &testpaths.file) {
+        inputs.push(mtime(&file));
+    }
     // Relevant pretty printer files
     let pretty_printer_files = [
         "src/etc/debugger_pretty_printers_common.py",
@@ -725,15 +1483,28 @@ fn up_to_date(
             entries.extend(path.read_dir().unwrap());
         }
     }
+    if let (Pretty, Some(ref reference_rustc)) = (config.mode, &config.reference_rustc) {
+        inputs.push(mtime(reference_rustc));
+    }
     if let Some(ref rustdoc_path) = config.rustdoc_path {
         inputs.push(mtime(&rustdoc_path));
         inputs.push(mtime(&rust_src_dir.join("src/etc/htmldocck.py")));
     }
 
-    // UI test files.
+    // UI test files, including the ones only used on this target or stage.
+    let mut targets = vec![None];
+    targets.extend(config.output_targets().into_iter().map(Some));
     for extension in UI_EXTENSIONS {
-        let path = &expected_output_path(testpaths, revision, &config.compare_mode, extension);
-        inputs.push(mtime(path));
+        for target in targets.iter() {
+            let path = &expected_output_path(
+                testpaths,
+                revision,
+                &config.compare_mode,
+                *target,
+                extension,
+            );
+            inputs.push(mtime(path));
+        }
     }
 
     inputs.iter().any(|input| *input > stamp)
@@ -773,13 +1544,29 @@ fn make_test_closure(
     config: &Config,
     testpaths: &TestPaths,
     revision: Option<&String>,
-) -> test::TestFn {
+    name: &test::TestName,
+    should_panic: test::ShouldPanic,
+    retries: usize,
+) -> order::Runner {
     let config = config.clone();
     let testpaths = testpaths.clone();
     let revision = revision.cloned();
-    test::DynTestFn(Box::new(move || {
-        runtest::run(config, &testpaths, revision.as_ref().map(|s| s.as_str()))
-    }))
+    let name = name.to_string();
+    Box::new(move || {
+        report::run_recorded(&config, &name, should_panic, || {
+            report::run_with_retries(&config, &name, retries, || {
+                runtest::run(config.clone(), &testpaths, revision.as_ref().map(|s| s.as_str()))
+            })
+        })
+    })
+}
+
+fn make_cached_closure(config: &Config, name: &test::TestName) -> order::Runner {
+    let config = config.clone();
+    let name = name.to_string();
+    Box::new(move || {
+        report::run_recorded(&config, &name, test::ShouldPanic::No, report::note_cached)
+    })
 }
 
 /// Returns (Path to GDB, GDB Version, GDB has Rust Support)
@@ -882,6 +1669,77 @@ fn extract_gdb_version(full_version_line: &str) -> Option<u32> {
     None
 }
 
+/// Returns the version of LLVM, as given with `--llvm-version` or else as
+/// reported by `rustc -vV`, in the encoding of `extract_llvm_version`.
+fn analyze_llvm(llvm_version: Option<String>, rustc_path: &Path) -> Option<u32> {
+    let version = match llvm_version {
+        Some(version) => version,
+        None => {
+            let output = Command::new(rustc_path).arg("-vV").output().ok()?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let line = stdout.lines().find(|l| l.starts_with("LLVM version:"))?;
+            line["LLVM version:".len()..].to_string()
+        }
+    };
+    extract_llvm_version(&version)
+}
+
+/// The `cfg`s tests are built with, as printed by `rustc --print cfg` when
+/// passed the same flags. Empty if that fails.
+fn rustc_cfg(rustc_path: &Path, target: &str, flags: &Option<String>) -> Vec<String> {
+    let mut rustc = Command::new(rustc_path);
+    rustc.args(&["--print", "cfg", "--target", target]);
+    if let Some(ref flags) = *flags {
+        rustc.args(flags.split_whitespace());
+    }
+    match rustc.output() {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `flags` ask rustc to optimize, with `-O` or `-C opt-level`.
+fn is_optimized(flags: &Option<String>) -> bool {
+    let flags = match *flags {
+        Some(ref flags) => flags.split_whitespace().collect::<Vec<_>>(),
+        None => return false,
+    };
+    let mut optimized = false;
+    for (i, flag) in flags.iter().enumerate() {
+        let level = if flag.starts_with("-Copt-level=") {
+            &flag["-Copt-level=".len()..]
+        } else if *flag == "-C" && flags.get(i + 1).map_or(false, |f| f.starts_with("opt-level=")) {
+            &flags[i + 1]["opt-level=".len()..]
+        } else if *flag == "-O" {
+            "2"
+        } else {
+            continue;
+        };
+        // The last one wins.
+        optimized = level != "0";
+    }
+    optimized
+}
+
+fn extract_llvm_version(version: &str) -> Option<u32> {
+    // LLVM versions look like "major.minor.patch?" followed by an optional
+    // suffix such as "svn" or "-rust-1.28.0-stable"
+    let version = version.trim();
+    let numeric = version
+        .find(|c: char| !c.is_digit(10) && c != '.')
+        .map_or(version, |idx| &version[..idx]);
+
+    let mut parts = numeric.split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+
+    Some(((major * 1000) + minor) * 1000 + patch)
+}
+
 fn extract_lldb_version(full_version_line: Option<String>) -> Option<String> {
     // Extract the major LLDB version from the given version string.
     // LLDB version strings are different for Apple and non-Apple platforms.
@@ -979,3 +1837,13 @@ fn test_extract_gdb_version() {
         7012050: "GNU gdb (GDB) 7.12.50.20161027-git",
     }
 }
+
+#[test]
+fn test_extract_llvm_version() {
+    assert_eq!(extract_llvm_version("6.0"), Some(6000000));
+    assert_eq!(extract_llvm_version("6.0.1\n"), Some(6000001));
+    assert_eq!(extract_llvm_version("7.0.0svn"), Some(7000000));
+    assert_eq!(extract_llvm_version("6.0.1-rust-1.28.0-stable"), Some(6000001));
+    assert_eq!(extract_llvm_version(" 10.0"), Some(10000000));
+    assert_eq!(extract_llvm_version("unknown"), None);
+}
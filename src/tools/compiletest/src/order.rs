@@ -0,0 +1,304 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tests that have to run after other tests.
+//!
+//! A test with an `after: other.rs` header depends on `other.rs` in the same
+//! directory, say because it uses something that test leaves behind. Tests
+//! linked that way form a group, which runs one test at a time, and never runs
+//! a test before the ones it depends on.
+//!
+//! libtest still picks the tests of a group in whatever order and on whichever
+//! thread it likes. So when it gets to a test whose dependencies haven't run
+//! yet, they are run first, right there, and once libtest gets to them, they
+//! just report how that went. As nothing ever waits for a test libtest hasn't
+//! started, this works with any number of threads.
+//!
+//! A test whose dependency failed fails without being run, and a test whose
+//! dependency is ignored is ignored too.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use test::{DynTestFn, ShouldPanic, TestDesc, TestDescAndFn};
+
+use common::{Config, TestPaths};
+use report;
+
+/// Runs a test, including recording its outcome.
+pub type Runner = Box<dyn Fn() + Send>;
+
+/// The tests, one per revision, made from one test file.
+pub struct Test {
+    pub paths: TestPaths,
+    /// The `after` headers of the file
+    pub after: Vec<String>,
+    pub entries: Vec<(TestDesc, Runner)>,
+}
+
+/// The tests for libtest to run, with the ones in groups made to run in order.
+pub fn order(config: &Config, tests: Vec<Test>) -> Vec<TestDescAndFn> {
+    let index: HashMap<PathBuf, usize> = tests
+        .iter()
+        .enumerate()
+        .map(|(i, test)| (test.paths.file.clone(), i))
+        .collect();
+    let deps: Vec<Vec<usize>> = tests
+        .iter()
+        .map(|test| {
+            let dir = test.paths.file.parent().unwrap();
+            test.after
+                .iter()
+                .map(|name| {
+                    *index.get(&dir.join(name)).unwrap_or_else(|| {
+                        panic!(
+                            "`after: {}` in {} doesn't name a test in the same directory",
+                            name,
+                            test.paths.file.display()
+                        )
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    let order = topological_order(&tests, &deps);
+    let groups = groups(&deps);
+    let mut tests: Vec<Option<Test>> = tests.into_iter().map(Some).collect();
+
+    // The dependencies come first, so it's known whether they're ignored.
+    let mut ignored = vec![false; tests.len()];
+    for &i in &order {
+        let test = tests[i].as_mut().unwrap();
+        if deps[i].iter().any(|&dep| ignored[dep]) {
+            for entry in &mut test.entries {
+                entry.0.ignore = true;
+            }
+        }
+        ignored[i] = test.entries.iter().any(|&(ref desc, _)| desc.ignore);
+    }
+
+    let mut result = Vec::new();
+    let mut members: HashMap<usize, Vec<Member>> = HashMap::new();
+    // The members of a group each test's entries are in, by test.
+    let mut positions: Vec<Vec<usize>> = vec![Vec::new(); tests.len()];
+    for i in order {
+        let test = tests[i].take().unwrap();
+        let group = match groups[i] {
+            Some(group) => members.entry(group).or_insert_with(Vec::new),
+            None => {
+                result.extend(test.entries.into_iter().map(|(desc, runner)| TestDescAndFn {
+                    desc,
+                    testfn: DynTestFn(Box::new(move || runner())),
+                }));
+                continue;
+            }
+        };
+        let member_deps: Vec<usize> =
+            deps[i].iter().flat_map(|&dep| positions[dep].clone()).collect();
+        for (desc, runner) in test.entries {
+            positions[i].push(group.len());
+            group.push(Member {
+                desc,
+                deps: member_deps.clone(),
+                runner,
+                outcome: None,
+            });
+        }
+    }
+
+    for (_, members) in members {
+        let descs: Vec<TestDesc> = members.iter().map(|m| m.desc.clone()).collect();
+        let group = Arc::new(Group {
+            config: config.clone(),
+            members: Mutex::new(members),
+        });
+        for (i, desc) in descs.into_iter().enumerate() {
+            let group = group.clone();
+            result.push(TestDescAndFn {
+                desc,
+                testfn: DynTestFn(Box::new(move || group.run(i))),
+            });
+        }
+    }
+    result
+}
+
+/// The tests in an order that puts every test after its dependencies.
+fn topological_order(tests: &[Test], deps: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut done = vec![false; tests.len()];
+    while order.len() < tests.len() {
+        let ready: Vec<usize> = (0..tests.len())
+            .filter(|&i| !done[i] && deps[i].iter().all(|&dep| done[dep]))
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<String> = (0..tests.len())
+                .filter(|&i| !done[i])
+                .map(|i| tests[i].paths.file.display().to_string())
+                .collect();
+            panic!(
+                "the `after` headers of these tests depend on each other:\n{}",
+                cycle.join("\n")
+            );
+        }
+        for i in ready {
+            done[i] = true;
+            order.push(i);
+        }
+    }
+    order
+}
+
+/// The group each test is in, if it is linked to another test.
+fn groups(deps: &[Vec<usize>]) -> Vec<Option<usize>> {
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut parent: Vec<usize> = (0..deps.len()).collect();
+    let mut linked = vec![false; deps.len()];
+    for (i, deps) in deps.iter().enumerate() {
+        for &dep in deps {
+            linked[i] = true;
+            linked[dep] = true;
+            let (a, b) = (root(&mut parent, i), root(&mut parent, dep));
+            parent[a] = b;
+        }
+    }
+    (0..deps.len())
+        .map(|i| if linked[i] { Some(root(&mut parent, i)) } else { None })
+        .collect()
+}
+
+struct Group {
+    config: Config,
+    /// In an order that puts every member after its dependencies
+    members: Mutex<Vec<Member>>,
+}
+
+struct Member {
+    desc: TestDesc,
+    /// The members that have to run first
+    deps: Vec<usize>,
+    runner: Runner,
+    outcome: Option<Outcome>,
+}
+
+struct Outcome {
+    /// The message of the panic the test ended with, if it did
+    panic: Option<String>,
+    passed: bool,
+}
+
+impl Group {
+    /// Runs member `i` after the members it depends on, unless it ran
+    /// already, and passes on its outcome.
+    fn run(&self, i: usize) {
+        let mut members = self.members.lock().unwrap();
+        let mut needed = vec![false; i + 1];
+        needed[i] = true;
+        for j in (0..i + 1).rev() {
+            if needed[j] {
+                for &dep in &members[j].deps {
+                    needed[dep] = true;
+                }
+            }
+        }
+        for j in 0..i + 1 {
+            if needed[j] && members[j].outcome.is_none() {
+                let outcome = self.run_member(&members, j);
+                members[j].outcome = Some(outcome);
+            }
+        }
+        // Panicking with the lock held would poison it for the rest of the
+        // group.
+        let panic = members[i].outcome.as_ref().unwrap().panic.clone();
+        drop(members);
+        if let Some(message) = panic {
+            panic!("{}", message);
+        }
+    }
+
+    fn run_member(&self, members: &[Member], i: usize) -> Outcome {
+        let member = &members[i];
+        let failed_dep = member
+            .deps
+            .iter()
+            .map(|&dep| &members[dep])
+            .find(|dep| !dep.outcome.as_ref().unwrap().passed);
+        let should_panic = member.desc.should_panic != ShouldPanic::No;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match failed_dep {
+            Some(dep) => {
+                let name = member.desc.name.to_string();
+                report::run_recorded(&self.config, &name, member.desc.should_panic, || {
+                    let message = format!("not run, as {} failed", dep.desc.name);
+                    // A test that should panic fails by not panicking.
+                    if should_panic {
+                        println!("{}", message);
+                    } else {
+                        panic!("{}", message);
+                    }
+                })
+            }
+            None => (member.runner)(),
+        }));
+        let panic = result.err().map(|payload| report::panic_message(&*payload));
+        Outcome {
+            passed: failed_dep.is_none() && panic.is_some() == should_panic,
+            panic,
+        }
+    }
+}
+
+#[test]
+fn test_failed_dependency() {
+    use common::KeepArtifacts;
+    use header::test_config;
+    use test::TestName;
+
+    fn member(name: &str, deps: Vec<usize>, runner: Runner) -> Member {
+        Member {
+            desc: TestDesc {
+                name: TestName::DynTestName(name.to_string()),
+                ignore: false,
+                should_panic: ShouldPanic::No,
+                allow_fail: false,
+            },
+            deps,
+            runner,
+            outcome: None,
+        }
+    }
+
+    let mut config = test_config();
+    config.keep_artifacts = KeepArtifacts::Never;
+    let group = Group {
+        config,
+        members: Mutex::new(vec![
+            member("dep", vec![], Box::new(|| panic!("dep broke"))),
+            member("first", vec![0], Box::new(|| ())),
+            member("second", vec![0], Box::new(|| ())),
+        ]),
+    };
+    let message = |i| {
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| group.run(i))).unwrap_err();
+        report::panic_message(&*payload)
+    };
+    assert_eq!(message(1), "not run, as dep failed");
+    assert_eq!(message(2), "not run, as dep failed");
+    assert_eq!(message(0), "dep broke");
+}
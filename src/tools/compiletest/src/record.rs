@@ -0,0 +1,196 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recording the processes tests run, and replaying them.
+//!
+//! With `--record-procs DIR`, every process a test runs through
+//! `compose_and_run` is written to a log for that test in `DIR`: its command
+//! line, the dylib search path it got, its input, its exit status and its
+//! output. With `--replay-procs DIR`, those processes aren't run at all;
+//! `compose_and_run` gets the recorded status and output instead, so that
+//! changes to how compiletest checks results can be tried out without a
+//! compiler, and a failure can be looked at again exactly as it happened.
+//!
+//! A replayed test has to run the same commands in the same order as when it
+//! was recorded. Tests that read files the compiler wrote, or run processes
+//! some other way (debuggers, `make`), can't be replayed.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use serde_json;
+
+use common::{Config, TestPaths};
+
+/// One process run by a test.
+#[derive(Serialize, Deserialize)]
+pub struct Interaction {
+    pub cmdline: String,
+    pub dylib_path: String,
+    pub input: Option<String>,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Interaction {
+    pub fn new(
+        cmdline: &str,
+        dylib_path: &str,
+        input: Option<&str>,
+        status: &ExitStatus,
+        stdout: &str,
+        stderr: &str,
+    ) -> Interaction {
+        Interaction {
+            cmdline: cmdline.to_owned(),
+            dylib_path: dylib_path.to_owned(),
+            input: input.map(str::to_owned),
+            code: status.code(),
+            signal: signal(status),
+            stdout: stdout.to_owned(),
+            stderr: stderr.to_owned(),
+        }
+    }
+
+    /// The exit status the process was recorded with.
+    pub fn status(&self) -> ExitStatus {
+        exit_status(self.code, self.signal)
+    }
+}
+
+enum Log {
+    Record(PathBuf),
+    Replay(PathBuf, VecDeque<Interaction>),
+}
+
+thread_local! {
+    // libtest runs each test on its own thread, so this only ever holds the
+    // log of the test currently running on this thread.
+    static LOG: RefCell<Option<Log>> = RefCell::new(None);
+}
+
+/// The log of the processes run by the test at `testpaths`, in `dir`.
+fn log_path(dir: &Path, testpaths: &TestPaths, revision: Option<&str>) -> PathBuf {
+    let mut name = testpaths.file.file_name().unwrap().to_str().unwrap().to_owned();
+    if let Some(revision) = revision {
+        name.push('.');
+        name.push_str(revision);
+    }
+    name.push_str(".procs.json");
+    dir.join(&testpaths.relative_dir).join(name)
+}
+
+/// Starts recording or replaying the processes of the test at `testpaths`
+/// on this thread, if `--record-procs` or `--replay-procs` was given.
+pub fn start(config: &Config, testpaths: &TestPaths, revision: Option<&str>) {
+    let log = if let Some(ref dir) = config.record_procs {
+        let path = log_path(dir, testpaths, revision);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path)
+            .unwrap_or_else(|e| panic!("couldn't create {}: {}", path.display(), e));
+        Some(Log::Record(path))
+    } else if let Some(ref dir) = config.replay_procs {
+        let path = log_path(dir, testpaths, revision);
+        let file = File::open(&path)
+            .unwrap_or_else(|e| panic!("couldn't open {}: {}", path.display(), e));
+        let interactions = BufReader::new(file)
+            .lines()
+            .map(|line| {
+                let line = line.unwrap();
+                serde_json::from_str(&line)
+                    .unwrap_or_else(|e| panic!("malformed log {}: {}", path.display(), e))
+            })
+            .collect();
+        Some(Log::Replay(path, interactions))
+    } else {
+        None
+    };
+    LOG.with(|l| *l.borrow_mut() = log);
+}
+
+/// Whether the processes of the current test are replayed rather than run.
+pub fn replaying() -> bool {
+    LOG.with(|l| match *l.borrow() {
+        Some(Log::Replay(..)) => true,
+        _ => false,
+    })
+}
+
+/// Adds `interaction` to the log of the current test, if it is recorded.
+pub fn record(interaction: &Interaction) {
+    LOG.with(|l| {
+        if let Some(Log::Record(ref path)) = *l.borrow() {
+            let mut file = OpenOptions::new().append(true).open(path).unwrap();
+            writeln!(file, "{}", serde_json::to_string(interaction).unwrap()).unwrap();
+        }
+    })
+}
+
+/// The next process recorded for the current test, which has to have been
+/// run with `cmdline`.
+pub fn replay(cmdline: &str) -> Interaction {
+    LOG.with(|l| match *l.borrow_mut() {
+        Some(Log::Replay(ref path, ref mut interactions)) => {
+            let interaction = interactions.pop_front().unwrap_or_else(|| {
+                panic!(
+                    "no more processes recorded in {}, but the test runs `{}`",
+                    path.display(),
+                    cmdline
+                )
+            });
+            if interaction.cmdline != cmdline {
+                panic!(
+                    "the test runs `{}`, but `{}` was recorded in {}",
+                    cmdline,
+                    interaction.cmdline,
+                    path.display()
+                );
+            }
+            interaction
+        }
+        _ => panic!("not replaying any processes"),
+    })
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn exit_status(code: Option<i32>, signal: Option<i32>) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    // The raw status as `waitpid` reports it.
+    match (code, signal) {
+        (_, Some(signal)) => ExitStatus::from_raw(signal),
+        (code, None) => ExitStatus::from_raw((code.unwrap_or(0) & 0xff) << 8),
+    }
+}
+
+#[cfg(windows)]
+fn exit_status(code: Option<i32>, _signal: Option<i32>) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code.unwrap_or(0) as u32)
+}
@@ -0,0 +1,1004 @@
+// Copyright 2018 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Machine-readable per-test results.
+//!
+//! When `--format json` is passed, every test run through `run_recorded`
+//! prints one line of JSON to stdout once it finishes, next to the events
+//! libtest emits itself. The record points at the files `runtest` dumped the
+//! output of the last executed process to, rather than inlining that output.
+//!
+//! Tests that only passed after being retried are remembered as flaky and
+//! listed by `print_flaky` once the suite is done, and `print_core_dumps` lists
+//! the core dumps collected with `--collect-core-dumps`. With `--verbose`, a line
+//! with the outcome of every test and the number of tests done so far is
+//! printed as they finish.
+//!
+//! Results are also collected for the whole suite. `write_junit` writes them
+//! out as JUnit XML for `--junit-report`, and `write_last_failed` remembers the
+//! failed tests in the build directory so that `--resume` can rerun just those,
+//! leaving out the quarantined ones.
+//! `write_html` renders them as a browsable page for `--html-report`,
+//! including the output and the mismatching expected/actual files of each test.
+//! `print_summary` prints the counts, timings and failures of the suite once
+//! it is done, and `write_summary_json` collects the same per mode and suite
+//! in the JSON file given to `--summary-json`.
+//!
+//! With `--bench-compile N`, the times of the compilations noted with
+//! `note_compile_sample` are written out by `write_compile_bench` as CSV,
+//! with the fastest, median and slowest of the runs of each test.
+//!
+//! Every failing test also gets a `<test>.failure.json` next to its output
+//! dumps, holding its headers, the processes it ran with their full output and
+//! the mismatches it found, unless `--keep-artifacts never` is in effect.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diff;
+use serde_json;
+use test::{OutputFormat, ShouldPanic};
+
+use common::{Config, KeepArtifacts};
+use util::{self, Color};
+
+#[derive(Default)]
+struct Notes {
+    cmdline: Option<String>,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    failure: String,
+    flaky: bool,
+    cached: bool,
+    compile_time: Duration,
+    run_time: Duration,
+    max_rss: Option<u64>,
+    diffs: Vec<Diff>,
+    processes: Vec<Process>,
+    headers: Vec<String>,
+    failure_bundle: Option<PathBuf>,
+    compile_samples: Vec<CompileSample>,
+    binary_size: Option<u64>,
+    metadata_comparison: Option<(Duration, Duration)>,
+}
+
+/// How long one compilation of a test took, as a whole and in each of the
+/// passes the compiler reported.
+struct CompileSample {
+    wall_time: Duration,
+    passes: Vec<(String, Duration)>,
+}
+
+/// Expected and actual contents of something a test compares, such as its
+/// stderr or pretty-printed source, that didn't match.
+#[derive(Clone, Serialize)]
+struct Diff {
+    kind: String,
+    expected: String,
+    actual: String,
+}
+
+/// A process the test ran and how it went.
+#[derive(Serialize)]
+struct Process {
+    cmdline: String,
+    status: String,
+    stdout: String,
+    stderr: String,
+}
+
+/// Everything known about a failed test, written to `<test>.failure.json`.
+#[derive(Serialize)]
+struct FailureBundle<'a> {
+    name: &'a str,
+    mode: String,
+    message: &'a str,
+    headers: &'a [String],
+    processes: &'a [Process],
+    diffs: &'a [Diff],
+}
+
+thread_local! {
+    // libtest runs each test on its own thread, so this only ever holds notes
+    // about the test currently running on this thread.
+    static NOTES: RefCell<Notes> = RefCell::new(Notes::default());
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    name: &'a str,
+    mode: String,
+    status: &'static str,
+    duration_ms: u64,
+    compile_ms: u64,
+    run_ms: u64,
+    max_rss_kb: Option<u64>,
+    binary_size: Option<u64>,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    cmdline: Option<String>,
+}
+
+/// Remember the command line of the process the current test just executed.
+pub fn note_command(cmdline: &str) {
+    NOTES.with(|n| n.borrow_mut().cmdline = Some(cmdline.to_string()));
+}
+
+/// Remember where the output of the last executed process was written.
+pub fn note_output(stdout: &Path, stderr: &Path) {
+    NOTES.with(|n| {
+        let mut n = n.borrow_mut();
+        n.stdout = Some(stdout.to_path_buf());
+        n.stderr = Some(stderr.to_path_buf());
+    });
+}
+
+/// Remember a process the current test ran, for the failure bundle.
+pub fn note_process(cmdline: &str, status: &str, stdout: &str, stderr: &str) {
+    NOTES.with(|n| {
+        n.borrow_mut().processes.push(Process {
+            cmdline: cmdline.to_string(),
+            status: status.to_string(),
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        })
+    });
+}
+
+/// Remember the headers of the current test and where to write its failure
+/// bundle, should it fail.
+pub fn note_test(headers: Vec<String>, failure_bundle: &Path) {
+    NOTES.with(|n| {
+        let mut n = n.borrow_mut();
+        n.headers = headers;
+        n.failure_bundle = Some(failure_bundle.to_path_buf());
+    });
+}
+
+/// Remember that the current test wasn't run again, as it passed before and
+/// nothing it depends on changed since.
+pub fn note_cached() {
+    NOTES.with(|n| n.borrow_mut().cached = true);
+}
+
+/// Account `d` to the time the current test spent compiling.
+pub fn note_compile_time(d: Duration) {
+    NOTES.with(|n| n.borrow_mut().compile_time += d);
+}
+
+/// Account `d` to the time the current test spent running the compiled program.
+pub fn note_run_time(d: Duration) {
+    NOTES.with(|n| n.borrow_mut().run_time += d);
+}
+
+/// Remember that the `kind` output of the current test didn't match what was
+/// expected, for the HTML report.
+pub fn note_diff(kind: &str, expected: &str, actual: &str) {
+    NOTES.with(|n| {
+        n.borrow_mut().diffs.push(Diff {
+            kind: kind.to_string(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        })
+    });
+}
+
+/// Remember how long a compilation of the current test took, for
+/// `--bench-compile`.
+pub fn note_compile_sample(wall_time: Duration, passes: Vec<(String, Duration)>) {
+    NOTES.with(|n| n.borrow_mut().compile_samples.push(CompileSample { wall_time, passes }));
+}
+
+/// Remember how long checking the current test with `--emit=metadata` and
+/// building it took, for `--compare-compile-fail-metadata`.
+pub fn note_metadata_comparison(metadata: Duration, full: Duration) {
+    NOTES.with(|n| n.borrow_mut().metadata_comparison = Some((metadata, full)));
+}
+
+/// Account the peak memory use of a process the current test ran, in bytes.
+pub fn note_max_rss(bytes: u64) {
+    NOTES.with(|n| {
+        let mut n = n.borrow_mut();
+        n.max_rss = Some(n.max_rss.map_or(bytes, |m| cmp::max(m, bytes)));
+    });
+}
+
+/// Remember the size of the binary the current test built.
+pub fn note_binary_size(bytes: u64) {
+    NOTES.with(|n| n.borrow_mut().binary_size = Some(bytes));
+}
+
+/// Remember a failure message printed by the current test, to be used as the
+/// failure description in the JUnit report.
+pub fn note_failure(msg: &str) {
+    NOTES.with(|n| {
+        let mut n = n.borrow_mut();
+        if !n.failure.is_empty() && !n.failure.ends_with('\n') {
+            n.failure.push('\n');
+        }
+        n.failure.push_str(msg);
+    });
+}
+
+/// A finished test.
+struct Outcome {
+    name: String,
+    /// `ok`, `flaky`, `cached`, `failed`, or `quarantined` for failures that
+    /// don't fail the suite.
+    status: &'static str,
+    duration: Duration,
+    compile_time: Duration,
+    run_time: Duration,
+    message: String,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    diffs: Vec<Diff>,
+    compile_samples: Vec<CompileSample>,
+    binary_size: Option<u64>,
+    /// How long checking the test with `--emit=metadata` and building it took.
+    metadata_comparison: Option<(Duration, Duration)>,
+}
+
+impl Outcome {
+    /// Whether the test failed the suite.
+    fn failed(&self) -> bool {
+        self.status == "failed"
+    }
+
+    /// Whether the test failed, quarantined or not.
+    fn failed_at_all(&self) -> bool {
+        self.failed() || self.status == "quarantined"
+    }
+}
+
+lazy_static! {
+    static ref OUTCOMES: Mutex<Vec<Outcome>> = Mutex::new(Vec::new());
+    static ref FLAKY: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static ref CORE_DUMPS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    static ref QUARANTINED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: AtomicUsize = AtomicUsize::new(0);
+static IGNORED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set how many tests the suite is going to run, for reporting progress.
+pub fn set_total(n: usize) {
+    TOTAL.store(n, Ordering::SeqCst);
+}
+
+/// Set how many tests the suite is going to skip as ignored, for the summary.
+pub fn set_ignored(n: usize) {
+    IGNORED.store(n, Ordering::SeqCst);
+}
+
+/// Run the test body `f`, running it again up to `retries` more times if it
+/// fails. Only the last attempt's panic, if any, is propagated.
+pub fn run_with_retries<F: Fn()>(config: &Config, name: &str, retries: usize, f: F) {
+    for attempt in 1..retries + 1 {
+        if panic::catch_unwind(AssertUnwindSafe(&f)).is_ok() {
+            if attempt > 1 {
+                mark_flaky(name);
+            }
+            return;
+        }
+        let msg = format!("attempt {} of {} failed, retrying", attempt, retries + 1);
+        println!("\n{}", util::paint(config, Color::Yellow, &msg));
+        NOTES.with(|n| {
+            let mut n = n.borrow_mut();
+            n.failure.clear();
+            n.processes.clear();
+        });
+    }
+    f();
+    if retries > 0 {
+        mark_flaky(name);
+    }
+}
+
+/// Write everything known about a failed test next to its output dumps, so
+/// that CI can keep a single file per failure.
+fn write_failure_bundle(config: &Config, name: &str, message: &str, notes: &Notes) {
+    let path = match notes.failure_bundle {
+        Some(ref path) => path,
+        None => return,
+    };
+    let bundle = FailureBundle {
+        name,
+        mode: config.mode.to_string(),
+        message,
+        headers: &notes.headers,
+        processes: &notes.processes,
+        diffs: &notes.diffs,
+    };
+    let json = serde_json::to_string_pretty(&bundle).unwrap();
+    if let Err(e) = fs::write(path, json) {
+        println!("failed to write {}: {}", path.display(), e);
+    }
+}
+
+fn mark_flaky(name: &str) {
+    NOTES.with(|n| n.borrow_mut().flaky = true);
+    FLAKY.lock().unwrap().push(name.to_string());
+}
+
+/// Print the tests that needed a retry to pass, if there were any.
+pub fn print_flaky(config: &Config) {
+    let mut flaky = FLAKY.lock().unwrap();
+    if flaky.is_empty() {
+        return;
+    }
+    flaky.sort();
+    println!(
+        "{}",
+        util::paint(config, Color::Yellow, "flaky tests (passed only after a retry):")
+    );
+    for name in flaky.iter() {
+        println!("    {}", name);
+    }
+    println!();
+}
+
+/// Remember the core dump of a process of the current test that crashed.
+pub fn note_core_dump(path: &Path) {
+    note_failure(&format!("core dump: {}", path.display()));
+    CORE_DUMPS.lock().unwrap().push(path.to_path_buf());
+}
+
+/// Print where the core dumps of crashed processes were put, if any were.
+pub fn print_core_dumps(config: &Config) {
+    let mut dumps = CORE_DUMPS.lock().unwrap();
+    if dumps.is_empty() {
+        return;
+    }
+    dumps.sort();
+    println!("{}", util::paint(config, Color::Yellow, "core dumps of crashed processes:"));
+    for path in dumps.iter() {
+        println!("    {}", path.display());
+    }
+    println!();
+}
+
+/// Set the tests whose failures don't fail the suite, with `--quarantine`.
+pub fn set_quarantined(names: HashSet<String>) {
+    *QUARANTINED.lock().unwrap() = names;
+}
+
+/// Run the test body `f` and record its outcome, emitting a result record for
+/// it if the configured output format asks for one. Panics from `f` are
+/// propagated unchanged so that libtest still sees the real outcome, unless
+/// `--fail-fast` is in effect, in which case the whole process exits.
+pub fn run_recorded<F: FnOnce()>(config: &Config, name: &str, should_panic: ShouldPanic, f: F) {
+    NOTES.with(|n| *n.borrow_mut() = Notes::default());
+    let start = Instant::now();
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    let elapsed = start.elapsed();
+
+    let failed = result.is_err() != (should_panic != ShouldPanic::No);
+    let notes = NOTES.with(|n| ::std::mem::replace(&mut *n.borrow_mut(), Notes::default()));
+
+    let mut message = notes.failure.clone();
+    if failed && message.is_empty() {
+        message = match result {
+            Ok(()) => "test did not panic as expected".to_string(),
+            Err(ref e) => panic_message(&**e),
+        };
+    }
+    if failed && config.keep_artifacts != KeepArtifacts::Never {
+        write_failure_bundle(config, name, &message, &notes);
+    }
+    let quarantined = failed && QUARANTINED.lock().unwrap().contains(name);
+    let status = if quarantined {
+        "quarantined"
+    } else if failed {
+        "failed"
+    } else if notes.flaky {
+        "flaky"
+    } else if notes.cached {
+        "cached"
+    } else {
+        "ok"
+    };
+    OUTCOMES.lock().unwrap().push(Outcome {
+        name: name.to_string(),
+        status,
+        duration: elapsed,
+        compile_time: notes.compile_time,
+        run_time: notes.run_time,
+        message: message.clone(),
+        stdout: notes.stdout.clone(),
+        stderr: notes.stderr.clone(),
+        diffs: notes.diffs,
+        compile_samples: notes.compile_samples,
+        binary_size: notes.binary_size,
+        metadata_comparison: notes.metadata_comparison,
+    });
+
+    if config.is_verbose() && config.format != OutputFormat::Json {
+        print_progress(config, name, status);
+    }
+
+    if config.format == OutputFormat::Json {
+        let record = Record {
+            ty: "compiletest",
+            name,
+            mode: config.mode.to_string(),
+            status,
+            duration_ms: millis(elapsed),
+            compile_ms: millis(notes.compile_time),
+            run_ms: millis(notes.run_time),
+            max_rss_kb: notes.max_rss.map(|b| b / 1024),
+            binary_size: notes.binary_size,
+            // Depending on `--keep-artifacts`, the dumps may be gone already.
+            stdout: notes.stdout.filter(|p| p.exists()),
+            stderr: notes.stderr.filter(|p| p.exists()),
+            cmdline: notes.cmdline,
+        };
+
+        // Write straight to the process' stdout: libtest captures `print!`
+        // output of tests and would otherwise swallow the record.
+        let line = serde_json::to_string(&record).unwrap();
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+
+    if failed && !quarantined && config.fail_fast {
+        stop_early(config, name, &message);
+    }
+
+    if let Err(e) = result {
+        panic::resume_unwind(e);
+    }
+}
+
+/// Print the outcome of the test `name`, preceded by how many tests are done.
+fn print_progress(config: &Config, name: &str, status: &str) {
+    let finished = FINISHED.fetch_add(1, Ordering::SeqCst) + 1;
+    let total = TOTAL.load(Ordering::SeqCst);
+    let color = match status {
+        "failed" => Color::Red,
+        "flaky" | "quarantined" => Color::Yellow,
+        _ => Color::Green,
+    };
+    let width = total.to_string().len();
+    // Written straight to stdout, as libtest captures `print!` output.
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = writeln!(
+        stdout,
+        "[{:>width$}/{}] {} {}",
+        finished,
+        total,
+        util::paint(config, color, &format!("{:<6}", status)),
+        name,
+        width = width
+    );
+    let _ = stdout.flush();
+}
+
+/// Exit right away after the test `name` failed. libtest would only show the
+/// test's output once the whole suite finished, so print what we know here.
+fn stop_early(config: &Config, name: &str, message: &str) -> ! {
+    {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = writeln!(stdout, "\n---- {} ----\n{}", name, message);
+        let _ = writeln!(stdout, "stopping after the first failure (--fail-fast)");
+        let _ = stdout.flush();
+    }
+    let _ = write_last_failed(config);
+    if let Some(ref path) = config.junit_report {
+        let _ = write_junit(config, path);
+    }
+    if config.html_report {
+        let _ = write_html(config, &config.build_base.join("report.html"));
+    }
+    process::exit(101);
+}
+
+fn last_failed_path(config: &Config) -> PathBuf {
+    config.build_base.join("last-failed")
+}
+
+/// Remember the names of the tests that failed in this run, one per line.
+/// Quarantined tests are left out: they are known to fail, and `--resume` is
+/// for rerunning the failures that need fixing.
+pub fn write_last_failed(config: &Config) -> io::Result<()> {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut contents = String::new();
+    for outcome in outcomes.iter().filter(|o| o.failed()) {
+        contents.push_str(&outcome.name);
+        contents.push('\n');
+    }
+    fs::create_dir_all(&config.build_base)?;
+    fs::write(last_failed_path(config), contents)
+}
+
+/// The names of the tests that failed in the previous run, if any did.
+pub fn load_last_failed(config: &Config) -> HashSet<String> {
+    fs::read_to_string(last_failed_path(config))
+        .map(|s| s.lines().map(|l| l.to_string()).collect())
+        .unwrap_or_default()
+}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "test panicked".to_string()
+    }
+}
+
+/// Write the results collected so far as a JUnit XML test suite named after
+/// the directory the tests were collected from. Quarantined failures are
+/// written as skipped tests, so that they don't fail the suite there either.
+pub fn write_junit(config: &Config, path: &Path) -> io::Result<()> {
+    let suite = config
+        .src_base
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| config.mode.to_string());
+    let outcomes = OUTCOMES.lock().unwrap();
+    let failures = outcomes.iter().filter(|o| o.failed()).count();
+    let skipped = outcomes.iter().filter(|o| o.status == "quarantined").count();
+    let total: Duration = outcomes.iter().map(|o| o.duration).sum();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="{}" tests="{}" failures="{}" errors="0" skipped="{}" time="{}">"#,
+        xml_escape(&suite),
+        outcomes.len(),
+        failures,
+        skipped,
+        seconds(total)
+    )?;
+    for outcome in outcomes.iter() {
+        write!(
+            out,
+            r#"  <testcase classname="{}" name="{}" time="{}""#,
+            xml_escape(&suite),
+            xml_escape(&outcome.name),
+            seconds(outcome.duration)
+        )?;
+        let summary = outcome.message.lines().find(|l| !l.trim().is_empty());
+        if outcome.failed() {
+            writeln!(
+                out,
+                r#"><failure message="{}">{}</failure></testcase>"#,
+                xml_escape(summary.unwrap_or("test failed").trim()),
+                xml_escape(&outcome.message)
+            )?;
+        } else if outcome.status == "quarantined" {
+            writeln!(
+                out,
+                r#"><skipped message="quarantined: {}"/><system-out>{}</system-out></testcase>"#,
+                xml_escape(summary.unwrap_or("test failed").trim()),
+                xml_escape(&outcome.message)
+            )?;
+        } else {
+            writeln!(out, "/>")?;
+        }
+    }
+    writeln!(out, "</testsuite>")?;
+    out.flush()
+}
+
+/// Output files larger than this are cut short in the HTML report.
+const HTML_OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// Write the results collected so far as a single self-contained HTML page:
+/// a summary of the suite, then every test with its status, output and diffs,
+/// failed tests first.
+pub fn write_html(config: &Config, path: &Path) -> io::Result<()> {
+    let suite = format!("{} ({})", config.src_base.display(), config.mode);
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut sorted = outcomes.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| {
+        b.failed_at_all()
+            .cmp(&a.failed_at_all())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let total: Duration = outcomes.iter().map(|o| o.duration).sum();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, r#"<html><head><meta charset="utf-8">"#)?;
+    writeln!(out, "<title>{}</title>", xml_escape(&suite))?;
+    writeln!(out, "<style>{}</style></head><body>", HTML_STYLE)?;
+    writeln!(out, "<h1>{}</h1>", xml_escape(&suite))?;
+    writeln!(
+        out,
+        "<p>{} tests: {} passed, {} failed, {} quarantined failed, {} flaky, {} cached; \
+         {}s in total</p>",
+        outcomes.len(),
+        count("ok"),
+        count("failed"),
+        count("quarantined"),
+        count("flaky"),
+        count("cached"),
+        seconds(total)
+    )?;
+
+    for outcome in sorted {
+        writeln!(
+            out,
+            r#"<details class="{status}"{open}><summary><span class="status">{status}</span> {name} <span class="time">{time}s</span></summary>"#,
+            status = outcome.status,
+            open = if outcome.failed_at_all() { " open" } else { "" },
+            name = xml_escape(&outcome.name),
+            time = seconds(outcome.duration)
+        )?;
+        if outcome.failed_at_all() {
+            writeln!(out, "<pre>{}</pre>", xml_escape(&outcome.message))?;
+        }
+        for diff in &outcome.diffs {
+            write_html_diff(&mut out, diff)?;
+        }
+        if let Some(ref stdout) = outcome.stdout {
+            write_html_output(&mut out, "stdout", stdout)?;
+        }
+        if let Some(ref stderr) = outcome.stderr {
+            write_html_output(&mut out, "stderr", stderr)?;
+        }
+        writeln!(out, "</details>")?;
+    }
+    writeln!(out, "</body></html>")?;
+    out.flush()
+}
+
+const HTML_STYLE: &str = "\
+    body { font-family: sans-serif; }\
+    pre, table.diff { font-family: monospace; white-space: pre-wrap; }\
+    details { border-bottom: 1px solid #ddd; padding: 2px; }\
+    details details { margin-left: 2em; border: none; }\
+    .status { display: inline-block; width: 4em; font-weight: bold; }\
+    .ok .status { color: #080; } .failed .status { color: #c00; }\
+    .flaky .status, .quarantined .status { color: #b80; }\
+    .cached .status { color: #888; }\
+    .time { color: #888; }\
+    table.diff { border-collapse: collapse; width: 100%; table-layout: fixed; }\
+    table.diff td { vertical-align: top; padding: 0 4px; }\
+    td.del { background: #fdd; } td.ins { background: #dfd; }";
+
+/// Render `diff` as a table with the expected lines on the left and the actual
+/// lines on the right.
+fn write_html_diff<W: Write>(out: &mut W, diff: &Diff) -> io::Result<()> {
+    writeln!(out, "<details open><summary>{} differs</summary>", xml_escape(&diff.kind))?;
+    writeln!(out, r#"<table class="diff"><tr><th>expected</th><th>actual</th></tr>"#)?;
+    for line in diff::lines(&diff.expected, &diff.actual) {
+        let (left, right) = match line {
+            diff::Result::Left(l) => (Some(l), None),
+            diff::Result::Right(r) => (None, Some(r)),
+            diff::Result::Both(l, r) => (Some(l), Some(r)),
+        };
+        let class = match (left, right) {
+            (Some(_), None) => r#" class="del""#,
+            (None, Some(_)) => r#" class="ins""#,
+            _ => "",
+        };
+        writeln!(
+            out,
+            "<tr><td{class}>{}</td><td{class}>{}</td></tr>",
+            xml_escape(left.unwrap_or("")),
+            xml_escape(right.unwrap_or("")),
+            class = class
+        )?;
+    }
+    writeln!(out, "</table></details>")
+}
+
+/// Embed the output file at `path`, if there is one and it isn't empty.
+fn write_html_output<W: Write>(out: &mut W, kind: &str, path: &Path) -> io::Result<()> {
+    let contents = match fs::read(path) {
+        Ok(ref contents) if !contents.is_empty() => {
+            let end = cmp::min(contents.len(), HTML_OUTPUT_LIMIT);
+            let mut s = String::from_utf8_lossy(&contents[..end]).into_owned();
+            if end < contents.len() {
+                s.push_str(&format!("\n[{} more bytes]", contents.len() - end));
+            }
+            s
+        }
+        _ => return Ok(()),
+    };
+    writeln!(
+        out,
+        "<details><summary>{} ({})</summary><pre>{}</pre></details>",
+        kind,
+        xml_escape(&path.display().to_string()),
+        xml_escape(&contents)
+    )
+}
+
+/// Print the `n` tests that took the longest, slowest first.
+pub fn print_slowest(n: usize) {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut slowest = outcomes.iter().collect::<Vec<_>>();
+    slowest.sort_by(|a, b| b.duration.cmp(&a.duration));
+    slowest.truncate(n);
+    if slowest.is_empty() {
+        return;
+    }
+
+    println!("slowest tests (total / compile / run seconds):");
+    for outcome in slowest {
+        println!(
+            "    {:>9} {:>9} {:>9}  {}",
+            seconds(outcome.duration),
+            seconds(outcome.compile_time),
+            seconds(outcome.run_time),
+            outcome.name
+        );
+    }
+    println!();
+}
+
+/// Write the timings of all tests run so far as CSV.
+pub fn write_timings_csv(path: &Path) -> io::Result<()> {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "name,status,total_ms,compile_ms,run_ms")?;
+    for outcome in outcomes.iter() {
+        writeln!(
+            out,
+            "\"{}\",{},{},{},{}",
+            outcome.name.replace('"', "\"\""),
+            outcome.status,
+            millis(outcome.duration),
+            millis(outcome.compile_time),
+            millis(outcome.run_time)
+        )?;
+    }
+    out.flush()
+}
+
+/// Write the sizes of the binaries built by the tests run so far as CSV,
+/// sorted by test, so that the files of two runs can be compared line by line.
+pub fn write_binary_sizes_csv(path: &Path) -> io::Result<()> {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut sizes = outcomes
+        .iter()
+        .filter_map(|o| o.binary_size.map(|size| (&o.name, size)))
+        .collect::<Vec<_>>();
+    sizes.sort();
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "name,bytes")?;
+    for (name, size) in sizes {
+        writeln!(out, "\"{}\",{}", name.replace('"', "\"\""), size)?;
+    }
+    out.flush()
+}
+
+/// Write the compile times of all tests run so far with `--bench-compile` as
+/// CSV: for every test, a line for the whole compilation (`(wall)`) and one
+/// for each pass, each with the number of runs and the fastest, median and
+/// slowest time. The lines are sorted by test, so that the files of two runs
+/// can be compared line by line.
+pub fn write_compile_bench(path: &Path) -> io::Result<()> {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let mut outcomes = outcomes
+        .iter()
+        .filter(|o| !o.compile_samples.is_empty())
+        .collect::<Vec<_>>();
+    outcomes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "name,pass,runs,min_ms,median_ms,max_ms")?;
+    for outcome in outcomes {
+        let mut rows = vec![(
+            "(wall)".to_string(),
+            outcome.compile_samples.iter().map(|s| s.wall_time).collect::<Vec<_>>(),
+        )];
+        for sample in &outcome.compile_samples {
+            for &(ref pass, time) in &sample.passes {
+                match rows.iter().position(|row| row.0 == *pass) {
+                    Some(i) => rows[i].1.push(time),
+                    None => rows.push((pass.clone(), vec![time])),
+                }
+            }
+        }
+        for (pass, mut times) in rows {
+            times.sort();
+            writeln!(
+                out,
+                "\"{}\",\"{}\",{},{},{},{}",
+                outcome.name.replace('"', "\"\""),
+                pass.replace('"', "\"\""),
+                times.len(),
+                millis(times[0]),
+                millis(times[times.len() / 2]),
+                millis(times[times.len() - 1])
+            )?;
+        }
+    }
+    out.flush()
+}
+
+/// Counts and timings of the tests run so far, as printed by `print_summary`.
+#[derive(Serialize)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+    quarantined: usize,
+    ignored: usize,
+    flaky: usize,
+    cached: usize,
+    duration_ms: u64,
+    compile_ms: u64,
+    run_ms: u64,
+    failures: Vec<String>,
+    quarantined_failures: Vec<String>,
+    metadata_comparison: Option<MetadataComparison>,
+}
+
+/// The total time the tests compared by `--compare-compile-fail-metadata` took
+/// to be checked with `--emit=metadata` and to be built.
+#[derive(Serialize)]
+struct MetadataComparison {
+    tests: usize,
+    metadata_ms: u64,
+    full_ms: u64,
+}
+
+fn summarize(elapsed: Duration) -> Summary {
+    let outcomes = OUTCOMES.lock().unwrap();
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let names = |status| {
+        let mut names = outcomes
+            .iter()
+            .filter(|o| o.status == status)
+            .map(|o| o.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    let failures = names("failed");
+    let quarantined_failures = names("quarantined");
+    let compared = outcomes
+        .iter()
+        .filter_map(|o| o.metadata_comparison)
+        .collect::<Vec<_>>();
+    let metadata_comparison = if compared.is_empty() {
+        None
+    } else {
+        Some(MetadataComparison {
+            tests: compared.len(),
+            metadata_ms: millis(compared.iter().map(|&(metadata, _)| metadata).sum()),
+            full_ms: millis(compared.iter().map(|&(_, full)| full).sum()),
+        })
+    };
+    Summary {
+        passed: outcomes.iter().filter(|o| !o.failed_at_all()).count(),
+        failed: failures.len(),
+        quarantined: quarantined_failures.len(),
+        ignored: IGNORED.load(Ordering::SeqCst),
+        flaky: count("flaky"),
+        cached: count("cached"),
+        duration_ms: millis(elapsed),
+        compile_ms: millis(outcomes.iter().map(|o| o.compile_time).sum()),
+        run_ms: millis(outcomes.iter().map(|o| o.run_time).sum()),
+        failures,
+        quarantined_failures,
+        metadata_comparison,
+    }
+}
+
+/// The name the summary of this suite goes by, like `[ui (nll)] ui`.
+fn suite_name(config: &Config) -> String {
+    let suite = config.src_base.file_name().unwrap().to_string_lossy();
+    match config.compare_mode {
+        Some(ref mode) => format!("[{} ({})] {}", config.mode, mode.to_str(), suite),
+        None => format!("[{}] {}", config.mode, suite),
+    }
+}
+
+/// Print how many tests passed, failed and were skipped, how long compiling
+/// and running them took in total, and which ones failed.
+pub fn print_summary(config: &Config, elapsed: Duration) {
+    let summary = summarize(elapsed);
+    println!("summary of {}:", suite_name(config));
+    println!(
+        "    {} passed ({} flaky, {} cached), {} failed, {} quarantined failed, {} ignored",
+        summary.passed,
+        summary.flaky,
+        summary.cached,
+        summary.failed,
+        summary.quarantined,
+        summary.ignored
+    );
+    println!(
+        "    {}s total, {}s compiling, {}s running",
+        seconds(elapsed),
+        seconds(Duration::from_millis(summary.compile_ms)),
+        seconds(Duration::from_millis(summary.run_ms))
+    );
+    if let Some(ref c) = summary.metadata_comparison {
+        let saved = 100 * c.full_ms.saturating_sub(c.metadata_ms) / cmp::max(c.full_ms, 1);
+        println!(
+            "    {} tests checked with --emit=metadata in {}s, built in {}s ({}% saved)",
+            c.tests,
+            seconds(Duration::from_millis(c.metadata_ms)),
+            seconds(Duration::from_millis(c.full_ms)),
+            saved
+        );
+    }
+    if !summary.failures.is_empty() {
+        println!("{}", util::paint(config, Color::Red, "    failed tests:"));
+        for name in &summary.failures {
+            println!("        {}", name);
+        }
+    }
+    if !summary.quarantined_failures.is_empty() {
+        let header = "    failed quarantined tests (not failing the suite):";
+        println!("{}", util::paint(config, Color::Yellow, header));
+        for name in &summary.quarantined_failures {
+            println!("        {}", name);
+        }
+    }
+    println!();
+}
+
+/// Add the summary of this suite to the JSON object in `path`, replacing any
+/// earlier summary of the same suite, so that one file can collect the
+/// summaries of every mode run by a single `x.py test`.
+pub fn write_summary_json(config: &Config, elapsed: Duration, path: &Path) -> io::Result<()> {
+    let mut summaries = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&s).ok())
+        .unwrap_or_default();
+    let summary = serde_json::to_value(summarize(elapsed)).unwrap();
+    summaries.insert(suite_name(config), summary);
+    fs::write(path, serde_json::to_string_pretty(&summaries).unwrap())
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + u64::from(d.subsec_millis())
+}
+
+fn seconds(d: Duration) -> String {
+    format!("{}.{:03}", d.as_secs(), d.subsec_millis())
+}
+
+/// Escape `s` for use in XML text and attribute values, dropping characters
+/// that XML 1.0 cannot represent at all.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' | '\r' | '\t' => out.push(c),
+            c if c < ' ' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -8,36 +8,113 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use common::CompareMode;
+use aux_cache;
+use command::Command;
+use core_dump;
+use common::{CompareMode, KeepArtifacts};
 use common::{expected_output_path, UI_EXTENSIONS, UI_FIXED, UI_STDERR, UI_STDOUT};
+use common::{UI_RUN_STDERR, UI_RUN_STDOUT};
 use common::{output_base_dir, output_base_name, output_testname_unique};
-use common::{Codegen, CodegenUnits, DebugInfoGdb, DebugInfoLldb, Rustdoc};
+use common::{Assembly, Codegen, CodegenUnits, DebugInfoGdb, DebugInfoLldb, Rustdoc};
 use common::{CompileFail, ParseFail, Pretty, RunFail, RunPass, RunPassValgrind};
 use common::{Config, TestPaths};
-use common::{Incremental, MirOpt, RunMake, Ui};
+use common::{CoverageMap, CoverageRun, DocExamples, Incremental, MirOpt, RunMake, Ui};
+use common::{COVERAGE_MAP, COVERAGE_RUN};
+use coverage;
 use diff;
 use errors::{self, Error, ErrorKind};
+use examples;
 use filetime::FileTime;
-use header::TestProps;
+use header::{self, CheckedOutput, ErrorPatternMode, PassMode, TestProps};
+use job;
 use json;
 use regex::Regex;
+use record;
+use report;
 use rustfix::{apply_suggestions, get_suggestions_from_json, Filter};
-use util::{logv, PathBufExt};
+use util::{self, Color, PathBufExt};
 
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs::{self, create_dir_all, File};
 use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
 use std::io::{self, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::process::{Child, ExitStatus, Output, Stdio};
 use std::str;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use extract_gdb_version;
+#[cfg(unix)]
+use libc;
+
+/// The environment variables processes run by tests inherit from compiletest.
+/// Besides the basics, these are what the tools we run need to work on the
+/// various platforms, and what bootstrap sets for the tests.
+const ENV_ALLOWLIST: &[&str] = &[
+    "HOME",
+    "LANG",
+    "LC_ALL",
+    "LOGNAME",
+    "PATH",
+    "TEMP",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "USER",
+    // Set by bootstrap and compiletest itself.
+    "PROFILER_SUPPORT",
+    "RUSTC_BOOTSTRAP",
+    "RUST_TEST_TMPDIR",
+    "SANITIZER_SUPPORT",
+    "TARGET",
+    "TEST_DEVICE_ADDR",
+    "__COMPAT_LAYER",
+    // Apple SDKs.
+    "IPHONEOS_DEPLOYMENT_TARGET",
+    "MACOSX_DEPLOYMENT_TARGET",
+    "SDKROOT",
+    // Windows, and MSVC in particular.
+    "APPDATA",
+    "COMSPEC",
+    "INCLUDE",
+    "LIB",
+    "LIBPATH",
+    "LOCALAPPDATA",
+    "NUMBER_OF_PROCESSORS",
+    "PATHEXT",
+    "PROCESSOR_ARCHITECTURE",
+    "PROGRAMDATA",
+    "PROGRAMFILES",
+    "PROGRAMFILES(X86)",
+    "SYSTEMDRIVE",
+    "SYSTEMROOT",
+    "UCRTVERSION",
+    "UNIVERSALCRTSDKDIR",
+    "USERPROFILE",
+    "VCINSTALLDIR",
+    "VCTOOLSINSTALLDIR",
+    "VSINSTALLDIR",
+    "WINDIR",
+    "WINDOWSSDKDIR",
+    "WINDOWSSDKVERSION",
+];
+
+/// Whether two environment variable names are the same, which on Windows
+/// disregards case.
+fn env_name_eq(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
 
 #[cfg(windows)]
 fn disable_error_reporting<F: FnOnce() -> R, R>(f: F) -> R {
@@ -70,6 +147,48 @@ fn disable_error_reporting<F: FnOnce() -> R, R>(f: F) -> R {
     f()
 }
 
+/// Kills the process `pid` once `timeout` elapses, unless the returned sender
+/// is used or dropped before that. The thread yields whether it killed it.
+fn spawn_watchdog(pid: u32, timeout: Duration) -> (Sender<()>, thread::JoinHandle<bool>) {
+    let (tx, rx) = channel();
+    let handle = thread::spawn(move || match rx.recv_timeout(timeout) {
+        Err(RecvTimeoutError::Timeout) => {
+            job::kill(pid);
+            true
+        }
+        _ => false,
+    });
+    (tx, handle)
+}
+
+/// The name of the signal that terminated a process, if a signal did.
+#[cfg(unix)]
+fn termination_signal(status: &ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal().map(|signal| {
+        let name = match signal {
+            libc::SIGABRT => "SIGABRT",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGILL => "SIGILL",
+            libc::SIGINT => "SIGINT",
+            libc::SIGKILL => "SIGKILL",
+            libc::SIGPIPE => "SIGPIPE",
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGTRAP => "SIGTRAP",
+            _ => return format!("signal {}", signal),
+        };
+        name.to_string()
+    })
+}
+
+#[cfg(not(unix))]
+fn termination_signal(_status: &ExitStatus) -> Option<String> {
+    None
+}
+
 /// The name of the environment variable that holds dynamic library locations.
 pub fn dylib_env_var() -> &'static str {
     if cfg!(windows) {
@@ -83,6 +202,18 @@ pub fn dylib_env_var() -> &'static str {
     }
 }
 
+/// The dylib search path of a process that needs both `lib_path` and
+/// `aux_path`, in front of the one compiletest runs with.
+fn dylib_search_path(lib_path: &str, aux_path: Option<&str>) -> OsString {
+    let mut path = env::split_paths(&env::var_os(dylib_env_var()).unwrap_or(OsString::new()))
+        .collect::<Vec<_>>();
+    if let Some(p) = aux_path {
+        path.insert(0, PathBuf::from(p))
+    }
+    path.insert(0, PathBuf::from(lib_path));
+    env::join_paths(&path).unwrap()
+}
+
 #[derive(Debug, PartialEq)]
 pub enum DiffLine {
     Context(String),
@@ -165,6 +296,59 @@ pub fn make_diff(expected: &str, actual: &str, context_size: usize) -> Vec<Misma
     results
 }
 
+/// Renders the differences between `expected` and `actual` as a unified diff,
+/// in ANSI colors if `color` is set.
+pub fn unified_diff(expected: &str, actual: &str, context_size: usize, color: bool) -> String {
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m\n", code, line)
+        } else {
+            line + "\n"
+        }
+    };
+
+    let mut out = String::new();
+    out.push_str(&paint("1", "--- expected".to_string()));
+    out.push_str(&paint("1", "+++ actual".to_string()));
+    // How many more lines `actual` has than `expected` before the current hunk.
+    let mut offset = 0i64;
+    for mismatch in make_diff(expected, actual, context_size) {
+        let mut expected_len = 0;
+        let mut actual_len = 0;
+        for line in &mismatch.lines {
+            match *line {
+                DiffLine::Context(_) => {
+                    expected_len += 1;
+                    actual_len += 1;
+                }
+                DiffLine::Expected(_) => expected_len += 1,
+                DiffLine::Resulting(_) => actual_len += 1,
+            }
+        }
+        let expected_start = i64::from(mismatch.line_number);
+        out.push_str(&paint(
+            "36",
+            format!(
+                "@@ -{},{} +{},{} @@",
+                expected_start,
+                expected_len,
+                expected_start + offset,
+                actual_len
+            ),
+        ));
+        offset += actual_len - expected_len;
+
+        for line in mismatch.lines {
+            out.push_str(&match line {
+                DiffLine::Context(c) => format!(" {}\n", c),
+                DiffLine::Expected(e) => paint("31", format!("-{}", e)),
+                DiffLine::Resulting(r) => paint("32", format!("+{}", r)),
+            });
+        }
+    }
+    out
+}
+
 pub fn run(config: Config, testpaths: &TestPaths, revision: Option<&str>) {
     match &*config.target {
         "arm-linux-androideabi" | "armv7-linux-androideabi" | "aarch64-linux-android" => {
@@ -181,7 +365,7 @@ pub fn run(config: Config, testpaths: &TestPaths, revision: Option<&str>) {
         }
     }
 
-    if config.verbose {
+    if config.is_very_verbose() {
         // We're going to be dumping a lot of info. Start on a new line.
         print!("\n\n");
     }
@@ -195,6 +379,17 @@ pub fn run(config: Config, testpaths: &TestPaths, revision: Option<&str>) {
         revision: revision,
     };
     create_dir_all(&cx.output_base_dir()).unwrap();
+    // Don't let a previous, failed run leave anything behind for this one.
+    let _ = fs::remove_dir_all(cx.scratch_dir());
+    let _cleanup = ArtifactCleanup {
+        dir: cx.output_base_dir(),
+        keep: config.keep_artifacts,
+    };
+    report::note_test(
+        header::header_lines(&config, &testpaths.file, revision),
+        &cx.make_out_name("failure.json"),
+    );
+    record::start(&config, testpaths, revision);
 
     if config.mode == Incremental {
         // Incremental tests are special because they cannot be run in
@@ -218,12 +413,138 @@ pub fn run(config: Config, testpaths: &TestPaths, revision: Option<&str>) {
         cx.run_revision();
     }
 
-    cx.create_stamp();
+    // A benchmarked test was only compiled, not tested.
+    if config.bench_compile.is_none() {
+        cx.create_stamp();
+    }
+}
+
+/// Removes everything but the stamp from a test's output directory once the
+/// test is done, unless `--keep-artifacts` says to keep it. Doing this on drop
+/// covers failing tests too, which bail out by panicking.
+struct ArtifactCleanup {
+    dir: PathBuf,
+    keep: KeepArtifacts,
+}
+
+impl Drop for ArtifactCleanup {
+    fn drop(&mut self) {
+        let keep = match self.keep {
+            KeepArtifacts::All => true,
+            KeepArtifacts::Failing => thread::panicking(),
+            KeepArtifacts::Never => false,
+        };
+        if keep {
+            return;
+        }
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        // Errors are ignored, leftovers only cost disk space.
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if entry.file_name() == *"stamp" {
+                continue;
+            } else if path.is_dir() {
+                let _ = fs::remove_dir_all(&path);
+            } else {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+/// The part of the compiler's stderr describing an internal compiler error,
+/// from the panic or ICE message through the backtrace, if there is one.
+fn ice_report(stderr: &str) -> Option<&str> {
+    const MARKERS: &[&str] = &[
+        "thread 'main' panicked at",
+        "thread 'rustc' panicked at",
+        "error: internal compiler error",
+    ];
+    MARKERS
+        .iter()
+        .filter_map(|marker| stderr.find(marker))
+        .min()
+        .map(|start| &stderr[start..])
+}
+
+/// The name of the library an `aux-native-lib` C file is built into, e.g.
+/// `foo` for `foo.c`.
+fn native_lib_name(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The names of the symbols `nm` lists as defined in `output`. Lines are like
+/// `0000000000001139 T main`; undefined symbols have no address and archives
+/// name each member on a line of its own.
+fn nm_symbols(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            match (columns.next(), columns.next(), columns.next()) {
+                (Some(_address), Some(kind), Some(name)) if kind != "U" => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
 }
 
-pub fn compute_stamp_hash(config: &Config) -> String {
+/// The names of the symbols in the map file written by the MSVC linker with
+/// `/MAP`, which has lines like
+/// `0001:00000010       _ZN4main4main17h0123456789abcdefE 0000000140001010 f   main.o`
+/// for both its public and static symbols: a section and offset, the name and
+/// the address.
+fn linker_map_symbols(map: &str) -> Vec<String> {
+    let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_digit(16));
+    map.lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let (offset, name, address) = (columns.next()?, columns.next()?, columns.next()?);
+            let mut offset = offset.splitn(2, ':');
+            let (section, offset) = (offset.next()?, offset.next()?);
+            if is_hex(section) && is_hex(offset) && is_hex(address) {
+                Some(name.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The environment variable telling the compiler and the test program about
+/// the test's scratch directory, see `TestCx::scratch_dir`.
+pub const SCRATCH_DIR_ENV: &str = "RUST_TEST_SCRATCH_DIR";
+
+/// Hashes everything a test's outcome depends on, besides the timestamps of
+/// the files `::up_to_date` looks at: the compiler stage, the flags and the
+/// contents of the test file itself.
+pub fn compute_stamp_hash(config: &Config, testpaths: &TestPaths) -> String {
     let mut hash = DefaultHasher::new();
     config.stage_id.hash(&mut hash);
+    config.target.hash(&mut hash);
+    config.host_rustcflags.hash(&mut hash);
+    config.target_rustcflags.hash(&mut hash);
+    config.compare_mode.as_ref().map(|m| m.to_str()).hash(&mut hash);
+    config.sanitizer.hash(&mut hash);
+    config.deny_warnings.hash(&mut hash);
+    config.compile_fail_metadata.hash(&mut hash);
+    config.compare_compile_fail_metadata.hash(&mut hash);
+    config.check_dep_info.hash(&mut hash);
+    if config.mode == Pretty {
+        config.reference_rustc.hash(&mut hash);
+    }
+    config.forced_headers.hash(&mut hash);
+    config.suppressed_headers.hash(&mut hash);
+    if let Ok(contents) = fs::read(&testpaths.file) {
+        contents.hash(&mut hash);
+    }
     format!("{:x}", hash.finish())
 }
 
@@ -244,6 +565,9 @@ impl<'test> TestCx<'test> {
     /// Code executed for each revision in turn (or, if there are no
     /// revisions, exactly once, with revision == None).
     fn run_revision(&self) {
+        if let Some(runs) = self.config.bench_compile {
+            return self.run_compile_bench(runs);
+        }
         match self.config.mode {
             CompileFail | ParseFail => self.run_cfail_test(),
             RunFail => self.run_rfail_test(),
@@ -259,10 +583,20 @@ impl<'test> TestCx<'test> {
             RunMake => self.run_rmake_test(),
             Ui => self.run_ui_test(),
             MirOpt => self.run_mir_opt_test(),
+            Assembly => self.run_assembly_test(),
+            CoverageMap | CoverageRun => self.run_coverage_test(),
+            DocExamples => self.run_doc_examples_test(),
         }
     }
 
     fn check_if_test_should_compile(&self, proc_res: &ProcRes) {
+        // Report a crash as such rather than as an unexpected exit status,
+        // unless crashing is what the test expects (rustdoc-ui tests use the
+        // same status for failing doctests).
+        if self.props.failure_status != 101 {
+            self.check_no_compiler_crash(proc_res);
+        }
+
         if self.props.compile_pass {
             if !proc_res.status.success() {
                 self.fatal_proc_rec("test compilation failed although it shouldn't!", proc_res);
@@ -281,12 +615,24 @@ impl<'test> TestCx<'test> {
 
     fn run_cfail_test(&self) {
         let proc_res = self.compile_test();
+        if self.config.compare_compile_fail_metadata && self.checks_metadata_only() {
+            self.compare_with_full_build();
+        }
+        if self.props.should_ice {
+            return self.check_compiler_ice(&proc_res);
+        }
         self.check_if_test_should_compile(&proc_res);
         self.check_no_compiler_crash(&proc_res);
 
         let output_to_check = self.get_output(&proc_res);
         let expected_errors = errors::load_errors(&self.testpaths.file, self.revision);
         if !expected_errors.is_empty() {
+            if self.props.known_bug.is_some() {
+                self.fatal(
+                    "`known-bug` tests record what the compiler does today, \
+                     so they can't have `//~` annotations of the errors it should report",
+                );
+            }
             if !self.props.error_patterns.is_empty() {
                 self.fatal("both error pattern and expected errors specified");
             }
@@ -306,29 +652,61 @@ impl<'test> TestCx<'test> {
         }
 
         let proc_res = self.exec_compiled_test();
-
-        // The value our Makefile configures valgrind to return on failure
-        const VALGRIND_ERR: i32 = 100;
-        if proc_res.status.code() == Some(VALGRIND_ERR) {
-            self.fatal_proc_rec("run-fail test isn't valgrind-clean!", &proc_res);
-        }
+        self.check_valgrind_clean(&proc_res);
+        self.check_sanitizer_clean(&proc_res);
 
         let output_to_check = self.get_output(&proc_res);
         self.check_correct_failure_status(&proc_res);
         self.check_error_patterns(&output_to_check, &proc_res);
+        self.check_forbid_output(&output_to_check, &proc_res);
     }
 
-    fn get_output(&self, proc_res: &ProcRes) -> String {
-        if self.props.check_stdout {
-            format!("{}{}", proc_res.stdout, proc_res.stderr)
-        } else {
-            proc_res.stderr.clone()
+    /// The outputs `error-pattern` and `forbid-output` look at, each of them
+    /// matched on its own.
+    fn get_output<'a>(&self, proc_res: &'a ProcRes) -> Vec<&'a str> {
+        match self.props.checked_output {
+            CheckedOutput::Stdout => vec![&proc_res.stdout[..]],
+            CheckedOutput::Stderr => vec![&proc_res.stderr[..]],
+            CheckedOutput::Both => vec![&proc_res.stdout[..], &proc_res.stderr[..]],
         }
     }
 
     fn check_correct_failure_status(&self, proc_res: &ProcRes) {
         let expected_status = Some(self.props.failure_status);
         let received_status = proc_res.status.code();
+        let received_signal = termination_signal(&proc_res.status);
+
+        if let Some(ref expected_signal) = self.props.failure_signal {
+            if received_signal.as_ref() != Some(expected_signal) {
+                self.fatal_proc_rec(
+                    &format!(
+                        "Error: expected the process to be terminated by {} but it {}.",
+                        expected_signal,
+                        proc_res.describe_status()
+                    ),
+                    proc_res,
+                );
+            }
+            return;
+        }
+
+        // Panics turn into traps on the bare wasm target, which the runtimes
+        // report with an exit status of their own choosing.
+        if expected_status == Some(101) && self.is_wasm_trap(proc_res) {
+            return;
+        }
+
+        // A crash isn't a failure with some unexpected status, say what it is.
+        if received_status.is_none() {
+            self.fatal_proc_rec(
+                &format!(
+                    "Error: expected failure status ({}) but the process {}.",
+                    self.props.failure_status,
+                    proc_res.describe_status()
+                ),
+                proc_res,
+            );
+        }
 
         if expected_status != received_status {
             self.fatal_proc_rec(
@@ -341,6 +719,13 @@ impl<'test> TestCx<'test> {
         }
     }
 
+    fn is_wasm_trap(&self, proc_res: &ProcRes) -> bool {
+        // nodejs reports a trap as a `RuntimeError`, wasmtime as a `wasm trap`.
+        self.config.target == "wasm32-unknown-unknown"
+            && !proc_res.status.success()
+            && (proc_res.stderr.contains("RuntimeError") || proc_res.stderr.contains("wasm trap"))
+    }
+
     fn run_rpass_test(&self) {
         let proc_res = self.compile_test();
 
@@ -355,19 +740,46 @@ impl<'test> TestCx<'test> {
             "run-pass tests with expected warnings should be moved to ui/"
         );
 
-        if !self.props.skip_codegen {
+        if !self.props.skip_codegen && self.props.pass_mode != Some(PassMode::Build) {
             let proc_res = self.exec_compiled_test();
+            self.check_sanitizer_clean(&proc_res);
             if !proc_res.status.success() {
                 self.fatal_proc_rec("test run failed!", &proc_res);
             }
+            if self.props.check_run_results {
+                self.check_run_results(&proc_res);
+            }
+        }
+    }
+
+    /// Compares the output of the compiled program against the expected
+    /// `*.run.stdout` and `*.run.stderr` files.
+    fn check_run_results(&self, proc_res: &ProcRes) {
+        let expected_stdout = self.load_expected_output(UI_RUN_STDOUT);
+        let expected_stderr = self.load_expected_output(UI_RUN_STDERR);
+        let normalized_stdout =
+            self.normalize_output(&proc_res.stdout, &self.props.normalize_stdout);
+        let normalized_stderr =
+            self.normalize_output(&proc_res.stderr, &self.props.normalize_stderr);
+
+        let mut errors = 0;
+        errors += self.compare_output(UI_RUN_STDOUT, &normalized_stdout, &expected_stdout);
+        errors += self.compare_output(UI_RUN_STDERR, &normalized_stderr, &expected_stderr);
+        if errors > 0 {
+            println!("To update references, rerun the tests and pass the `--bless` flag");
+            self.fatal_proc_rec(
+                &format!("{} errors occurred comparing run output.", errors),
+                proc_res,
+            );
         }
     }
 
     fn run_valgrind_test(&self) {
         assert!(self.revision.is_none(), "revisions not relevant here");
 
-        if self.config.valgrind_path.is_none() {
-            assert!(!self.config.force_valgrind);
+        // A sanitizer takes the place of valgrind, `run_rpass_test` checks it.
+        if self.config.valgrind_path.is_none() || self.config.sanitizer.is_some() {
+            assert!(!self.config.force_valgrind || self.config.sanitizer.is_some());
             return self.run_rpass_test();
         }
 
@@ -384,20 +796,81 @@ impl<'test> TestCx<'test> {
             ..*self
         };
         proc_res = new_cx.exec_compiled_test();
+        new_cx.check_valgrind_clean(&proc_res);
 
         if !proc_res.status.success() {
             self.fatal_proc_rec("test run failed!", &proc_res);
         }
     }
 
+    /// Fails the test if the sanitizer it was built with reported errors, as
+    /// opposed to the test failing by itself.
+    fn check_sanitizer_clean(&self, proc_res: &ProcRes) {
+        if self.config.sanitizer.is_none() {
+            return;
+        }
+        const REPORTS: &[&str] = &[
+            "ERROR: AddressSanitizer:",
+            "ERROR: LeakSanitizer:",
+            "WARNING: MemorySanitizer:",
+            "WARNING: ThreadSanitizer:",
+        ];
+        let report = proc_res
+            .stderr
+            .lines()
+            .find(|line| REPORTS.iter().any(|r| line.contains(r)));
+        if let Some(report) = report {
+            self.fatal_proc_rec(
+                &format!("test isn't memory-clean! {}", report.trim()),
+                proc_res,
+            );
+        }
+    }
+
+    /// The command the compiled test is run under, if any. A `runtool` header
+    /// takes precedence over the configured one.
+    fn runtool(&self) -> Option<String> {
+        match self.props.runtool {
+            Some(ref tool) if tool == "none" => None,
+            Some(ref tool) => Some(tool.clone()),
+            None => self.config.runtool.clone(),
+        }
+    }
+
+    /// Whether the compiled test is run under valgrind.
+    fn runs_under_valgrind(&self) -> bool {
+        self.split_maybe_args(&self.runtool())
+            .first()
+            .and_then(|tool| Path::new(tool).file_stem().map(|s| s == "valgrind"))
+            .unwrap_or(false)
+    }
+
+    fn valgrind_log_path(&self) -> PathBuf {
+        self.make_out_name("valgrind")
+    }
+
+    /// Fails the test, with valgrind's log attached, if valgrind found errors.
+    /// These are told apart from the test failing by itself by the exit code
+    /// valgrind is told to use in `make_run_args`.
+    fn check_valgrind_clean(&self, proc_res: &ProcRes) {
+        if !self.runs_under_valgrind()
+            || proc_res.status.code() != Some(self.config.valgrind_error_code)
+        {
+            return;
+        }
+        let log = fs::read_to_string(self.valgrind_log_path())
+            .unwrap_or_else(|e| format!("(failed to read the valgrind log: {})", e));
+        self.fatal_proc_rec(
+            &format!("test isn't valgrind-clean! valgrind log:\n{}", log),
+            proc_res,
+        );
+    }
+
     fn run_pretty_test(&self) {
         if self.props.pp_exact.is_some() {
-            logv(self.config, "testing for exact pretty-printing".to_owned());
+            debug!("testing for exact pretty-printing");
         } else {
-            logv(
-                self.config,
-                "testing for converging pretty-printing".to_owned(),
-            );
+            debug!("testing for converging pretty-printing");
         }
 
         let rounds = match self.props.pp_exact {
@@ -414,12 +887,9 @@ impl<'test> TestCx<'test> {
 
         let mut round = 0;
         while round < rounds {
-            logv(
-                self.config,
-                format!(
-                    "pretty-printing round {} revision {:?}",
-                    round, self.revision
-                ),
+            debug!(
+                "pretty-printing round {} revision {:?}",
+                round, self.revision
             );
             let proc_res = self.print_source(srcs[round].to_owned(), &self.props.pretty_mode);
 
@@ -461,17 +931,25 @@ impl<'test> TestCx<'test> {
 
         self.compare_source(&expected, &actual);
 
+        if self.config.reference_rustc.is_some() {
+            self.compare_with_reference(&srcs[0], &srcs[1]);
+        }
+
         // If we're only making sure that the output matches then just stop here
         if self.props.pretty_compare_only {
             return;
         }
 
         // Finally, let's make sure it actually appears to remain valid code
-        let proc_res = self.typecheck_source(actual);
+        let proc_res = self.typecheck_source(actual.clone());
         if !proc_res.status.success() {
             self.fatal_proc_rec("pretty-printed source does not typecheck", &proc_res);
         }
 
+        if self.props.pretty_run {
+            self.check_pretty_printed_runs_alike(&actual);
+        }
+
         if !self.props.pretty_expanded {
             return;
         }
@@ -498,7 +976,7 @@ impl<'test> TestCx<'test> {
     fn print_source(&self, src: String, pretty_type: &str) -> ProcRes {
         let aux_dir = self.aux_output_dir_name();
 
-        let mut rustc = Command::new(&self.config.rustc_path);
+        let mut rustc = self.command(&self.config.rustc_path);
         rustc
             .arg("-")
             .args(&["-Z", &format!("unpretty={}", pretty_type)])
@@ -507,7 +985,7 @@ impl<'test> TestCx<'test> {
             .arg(&aux_dir)
             .args(self.split_maybe_args(&self.config.target_rustcflags))
             .args(&self.props.compile_flags)
-            .envs(self.props.exec_env.clone());
+            .envs(self.props.rustc_env.clone());
 
         self.compose_and_run(
             rustc,
@@ -519,24 +997,105 @@ impl<'test> TestCx<'test> {
 
     fn compare_source(&self, expected: &str, actual: &str) {
         if expected != actual {
+            report::note_diff("pretty-printed source", expected, actual);
+            println!(
+                "\n{}",
+                unified_diff(expected, actual, 3, util::use_color(self.config))
+            );
+            self.fatal("pretty-printed source does not match expected source");
+        }
+    }
+
+    /// Pretty-prints `src` with the reference rustc and fails unless that
+    /// gives `printed`, what the compiler under test made of it.
+    fn compare_with_reference(&self, src: &str, printed: &str) {
+        let reference_rustc = self.config.reference_rustc.as_ref().unwrap();
+        let mut rustc = self.command(reference_rustc);
+        // Auxiliary crates built by the compiler under test are of no use to
+        // the reference compiler, but pretty-printing doesn't look at them.
+        rustc
+            .arg("-")
+            .args(&["-Z", &format!("unpretty={}", self.props.pretty_mode)])
+            .args(&["--target", &self.config.target])
+            .args(&self.props.compile_flags)
+            .envs(self.props.rustc_env.clone())
+            // The reference compiler is likely a release, where `-Z` is
+            // unstable.
+            .env("RUSTC_BOOTSTRAP", "1");
+        // The reference compiler comes with its own libraries.
+        let lib_path = reference_rustc
+            .parent()
+            .and_then(Path::parent)
+            .map_or(PathBuf::new(), |sysroot| sysroot.join("lib"));
+        let proc_res = self.compose_and_run(
+            rustc,
+            lib_path.to_str().unwrap(),
+            None,
+            Some(src.to_owned()),
+        );
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("pretty-printing with the reference rustc failed", &proc_res);
+        }
+
+        if proc_res.stdout != printed {
+            report::note_diff("pretty-printed source (reference rustc)", &proc_res.stdout, printed);
+            println!(
+                "\n{}",
+                unified_diff(&proc_res.stdout, printed, 3, util::use_color(self.config))
+            );
             self.fatal(&format!(
-                "pretty-printed source does not match expected source\n\
-                 expected:\n\
-                 ------------------------------------------\n\
-                 {}\n\
-                 ------------------------------------------\n\
-                 actual:\n\
-                 ------------------------------------------\n\
-                 {}\n\
-                 ------------------------------------------\n\
-                 \n",
-                expected, actual)
+                "pretty-printed source differs from what {} prints",
+                reference_rustc.display()
+            ));
+        }
+    }
+
+    /// Builds and runs both the test and its pretty-printed source, failing if
+    /// the two programs don't exit the same way and print the same output,
+    /// i.e. if the pretty printer changed the meaning of the code.
+    fn check_pretty_printed_runs_alike(&self, src: &str) {
+        let original = self.build_and_run(&self.testpaths.file, "original");
+        let pretty_file = self.make_out_name("pretty.rs");
+        fs::write(&pretty_file, src).unwrap();
+        let printed = self.build_and_run(&pretty_file, "pretty-printed");
+
+        if original.status.code() != printed.status.code() {
+            println!("original program {}", original.describe_status());
+            self.fatal_proc_rec(
+                "pretty-printed program exits differently than the original",
+                &printed,
+            );
+        }
+        if original.stdout != printed.stdout {
+            report::note_diff("program output", &original.stdout, &printed.stdout);
+            println!(
+                "\n{}",
+                unified_diff(
+                    &original.stdout,
+                    &printed.stdout,
+                    3,
+                    util::use_color(self.config)
+                )
+            );
+            self.fatal_proc_rec(
+                "pretty-printed program prints something else than the original",
+                &printed,
             );
         }
     }
 
+    fn build_and_run(&self, file: &Path, what: &str) -> ProcRes {
+        let mut rustc = self.make_compile_args(file, TargetLocation::ThisFile(self.make_exe_name()));
+        rustc.arg("-L").arg(&self.aux_output_dir_name());
+        let proc_res = self.compose_and_run_compiler(rustc, None);
+        if !proc_res.status.success() {
+            self.fatal_proc_rec(&format!("compiling the {} source failed", what), &proc_res);
+        }
+        self.exec_compiled_test()
+    }
+
     fn typecheck_source(&self, src: String) -> ProcRes {
-        let mut rustc = Command::new(&self.config.rustc_path);
+        let mut rustc = self.command(&self.config.rustc_path);
 
         let out_dir = self.output_base_name().with_extension("pretty-out");
         let _ = fs::remove_dir_all(&out_dir);
@@ -654,14 +1213,14 @@ impl<'test> TestCx<'test> {
 
                 let adb_path = &self.config.adb_path;
 
-                Command::new(adb_path)
+                self.command(adb_path)
                     .arg("push")
                     .arg(&exe_file)
                     .arg(&self.config.adb_test_dir)
                     .status()
                     .expect(&format!("failed to exec `{:?}`", adb_path));
 
-                Command::new(adb_path)
+                self.command(adb_path)
                     .args(&["forward", "tcp:5039", "tcp:5039"])
                     .status()
                     .expect(&format!("failed to exec `{:?}`", adb_path));
@@ -680,7 +1239,8 @@ impl<'test> TestCx<'test> {
                 );
 
                 debug!("adb arg: {}", adb_arg);
-                let mut adb = Command::new(adb_path)
+                let mut adb = self
+                    .command(adb_path)
                     .args(&["shell", &adb_arg])
                     .stdout(Stdio::piped())
                     .stderr(Stdio::inherit())
@@ -716,22 +1276,23 @@ impl<'test> TestCx<'test> {
                     status,
                     stdout,
                     stderr,
-                } = Command::new(&gdb_path)
+                } = self
+                    .command(&gdb_path)
                     .args(&debugger_opts)
                     .output()
                     .expect(&format!("failed to exec `{:?}`", gdb_path));
                 let cmdline = {
-                    let mut gdb = Command::new(&format!("{}-gdb", self.config.target));
+                    let mut gdb = self.command(&format!("{}-gdb", self.config.target));
                     gdb.args(&debugger_opts);
                     let cmdline = self.make_cmdline(&gdb, "");
-                    logv(self.config, format!("executing {}", cmdline));
+                    info!("executing {}", cmdline);
                     cmdline
                 };
 
                 debugger_run_result = ProcRes {
                     status,
-                    stdout: String::from_utf8(stdout).unwrap(),
-                    stderr: String::from_utf8(stderr).unwrap(),
+                    stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&stderr).into_owned(),
                     cmdline,
                 };
                 if adb.kill().is_err() {
@@ -822,7 +1383,7 @@ impl<'test> TestCx<'test> {
                     format!("-command={}", debugger_script.to_str().unwrap()),
                 ];
 
-                let mut gdb = Command::new(self.config.gdb.as_ref().unwrap());
+                let mut gdb = self.command(self.config.gdb.as_ref().unwrap());
                 gdb.args(&debugger_opts)
                     .env("PYTHONPATH", rust_pp_module_abs_path);
 
@@ -963,7 +1524,7 @@ impl<'test> TestCx<'test> {
         // Prepare the lldb_batchmode which executes the debugger script
         let lldb_script_path = rust_src_root.join("src/etc/lldb_batchmode.py");
         self.cmd2procres(
-            Command::new(&self.config.lldb_python)
+            self.command(&self.config.lldb_python)
                 .arg(&lldb_script_path)
                 .arg(test_executable)
                 .arg(debugger_script)
@@ -972,16 +1533,12 @@ impl<'test> TestCx<'test> {
     }
 
     fn cmd2procres(&self, cmd: &mut Command) -> ProcRes {
-        let (status, out, err) = match cmd.output() {
-            Ok(Output {
-                status,
-                stdout,
-                stderr,
-            }) => (
-                status,
-                String::from_utf8(stdout).unwrap(),
-                String::from_utf8(stderr).unwrap(),
-            ),
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = match cmd.output() {
+            Ok(output) => output,
             Err(e) => self.fatal(&format!(
                 "Failed to setup Python process for \
                  LLDB script: {}",
@@ -989,13 +1546,17 @@ impl<'test> TestCx<'test> {
             )),
         };
 
-        self.dump_output(&out, &err);
-        ProcRes {
+        let cmdline = format!("{:?}", cmd);
+        report::note_command(&cmdline);
+        self.dump_output(&stdout, &stderr);
+        let result = ProcRes {
             status,
-            stdout: out,
-            stderr: err,
-            cmdline: format!("{:?}", cmd),
-        }
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            cmdline,
+        };
+        result.note();
+        result
     }
 
     fn parse_debugger_commands(&self, debugger_prefixes: &[&str]) -> DebuggerCommands {
@@ -1125,7 +1686,7 @@ impl<'test> TestCx<'test> {
         }
     }
 
-    fn check_error_patterns(&self, output_to_check: &str, proc_res: &ProcRes) {
+    fn check_error_patterns(&self, output_to_check: &[&str], proc_res: &ProcRes) {
         if self.props.error_patterns.is_empty() {
             if self.props.compile_pass {
                 return;
@@ -1136,51 +1697,132 @@ impl<'test> TestCx<'test> {
                 ));
             }
         }
-        let mut next_err_idx = 0;
-        let mut next_err_pat = self.props.error_patterns[next_err_idx].trim();
-        let mut done = false;
-        for line in output_to_check.lines() {
-            if line.contains(next_err_pat) {
-                debug!("found error pattern {}", next_err_pat);
-                next_err_idx += 1;
-                if next_err_idx == self.props.error_patterns.len() {
-                    debug!("found all error patterns");
-                    done = true;
-                    break;
+        // No line spans two outputs, so neither does a match.
+        let lines = || output_to_check.iter().flat_map(|output| output.lines());
+        let missing_patterns = match self.props.error_pattern_mode {
+            ErrorPatternMode::Ordered => {
+                let mut patterns = self.props.error_patterns.iter().peekable();
+                for line in lines() {
+                    if patterns.peek().map_or(false, |p| line.contains(p.trim())) {
+                        debug!("found error pattern {}", patterns.next().unwrap());
+                    }
                 }
-                next_err_pat = self.props.error_patterns[next_err_idx].trim();
+                patterns
+                    .map(|p| format!("error pattern '{}' not found!", p))
+                    .collect::<Vec<_>>()
             }
-        }
-        if done {
-            return;
-        }
+            ErrorPatternMode::Unordered => self
+                .props
+                .error_patterns
+                .iter()
+                .filter(|p| !lines().any(|line| line.contains(p.trim())))
+                .map(|p| format!("error pattern '{}' not found!", p))
+                .collect(),
+            ErrorPatternMode::Exact => {
+                let mut patterns = self.props.error_patterns.clone();
+                patterns.sort();
+                patterns.dedup();
+                patterns
+                    .iter()
+                    .filter_map(|p| {
+                        let expected =
+                            self.props.error_patterns.iter().filter(|q| *q == p).count();
+                        let found = lines().filter(|line| line.contains(p.trim())).count();
+                        if found == expected {
+                            None
+                        } else {
+                            Some(format!(
+                                "error pattern '{}' found on {} lines, expected {}!",
+                                p, found, expected
+                            ))
+                        }
+                    })
+                    .collect()
+            }
+        };
 
-        let missing_patterns = &self.props.error_patterns[next_err_idx..];
-        if missing_patterns.len() == 1 {
-            self.fatal_proc_rec(
-                &format!("error pattern '{}' not found!", missing_patterns[0]),
-                proc_res,
-            );
+        if missing_patterns.is_empty() {
+            debug!("found all error patterns");
+        } else if missing_patterns.len() == 1 {
+            self.fatal_proc_rec(&missing_patterns[0], proc_res);
         } else {
-            for pattern in missing_patterns {
-                self.error(&format!("error pattern '{}' not found!", *pattern));
+            for msg in &missing_patterns {
+                self.error(msg);
             }
-            self.fatal_proc_rec("multiple error patterns not found", proc_res);
+            self.fatal_proc_rec("multiple error patterns not matched", proc_res);
         }
     }
 
     fn check_no_compiler_crash(&self, proc_res: &ProcRes) {
         match proc_res.status.code() {
-            Some(101) => self.fatal_proc_rec("compiler encountered internal error", proc_res),
-            None => self.fatal_proc_rec("compiler terminated by signal", proc_res),
+            Some(101) => match ice_report(&proc_res.stderr) {
+                Some(report) => {
+                    let path = self.save_ice_report(report);
+                    self.fatal_proc_rec(
+                        &format!(
+                            "compiler crashed with an internal compiler error, \
+                             its backtrace is saved in {}",
+                            path.display()
+                        ),
+                        proc_res,
+                    )
+                }
+                None => self.fatal_proc_rec("compiler encountered internal error", proc_res),
+            },
+            None => self.fatal_proc_rec(
+                &format!("compiler {}", proc_res.describe_status()),
+                proc_res,
+            ),
             _ => (),
         }
     }
 
-    fn check_forbid_output(&self, output_to_check: &str, proc_res: &ProcRes) {
+    /// Writes the part of the compiler's stderr about an ICE next to the test's
+    /// other output, as `testname.ice`, and returns where.
+    fn save_ice_report(&self, report: &str) -> PathBuf {
+        let extension = match self.revision {
+            Some(r) => format!("{}.ice", r),
+            None => "ice".to_string(),
+        };
+        self.dump_output_file(report, &extension);
+        self.make_out_name(&extension)
+    }
+
+    /// Checks the outcome of a `// should-ice` test: the compiler has to crash
+    /// with an internal compiler error, which must still match the error
+    /// patterns if the test has any.
+    fn check_compiler_ice(&self, proc_res: &ProcRes) {
+        let crashed =
+            proc_res.status.code() == Some(101) && ice_report(&proc_res.stderr).is_some();
+        if !crashed {
+            let outcome = if proc_res.status.success() {
+                "compiled successfully"
+            } else {
+                "failed without an internal compiler error"
+            };
+            self.fatal_proc_rec(
+                &format!(
+                    "test was expected to ICE but {}; if the crash is fixed, \
+                     remove the `should-ice` directive and turn this into a regular test",
+                    outcome
+                ),
+                proc_res,
+            );
+        }
+
+        if !self.props.error_patterns.is_empty() {
+            let output_to_check = self.get_output(proc_res);
+            self.check_error_patterns(&output_to_check, proc_res);
+        }
+    }
+
+    fn check_forbid_output(&self, output_to_check: &[&str], proc_res: &ProcRes) {
         for pat in &self.props.forbid_output {
-            if output_to_check.contains(pat) {
-                self.fatal_proc_rec("forbidden pattern found in compiler output", proc_res);
+            if output_to_check.iter().any(|output| output.contains(pat)) {
+                self.fatal_proc_rec(
+                    &format!("forbidden pattern found in output: {}", pat),
+                    proc_res,
+                );
             }
         }
     }
@@ -1305,6 +1947,22 @@ impl<'test> TestCx<'test> {
     }
 
     fn compile_test(&self) -> ProcRes {
+        let proc_res = self.compose_and_run_compiler(self.compile_test_command(), None);
+        if proc_res.status.success() {
+            self.check_emitted(&proc_res);
+            self.check_binary_size(&proc_res);
+            if !self.props.check_symbols.is_empty() || !self.props.check_no_symbols.is_empty() {
+                self.check_symbols(&proc_res);
+            }
+            if self.config.check_dep_info {
+                self.check_dep_info(&proc_res);
+            }
+        }
+        proc_res
+    }
+
+    /// The compiler invocation `compile_test` runs.
+    fn compile_test_command(&self) -> Command {
         let mut rustc = self.make_compile_args(
             &self.testpaths.file,
             TargetLocation::ThisFile(self.make_exe_name()),
@@ -1312,6 +1970,9 @@ impl<'test> TestCx<'test> {
 
         rustc.arg("-L").arg(&self.aux_output_dir_name());
 
+        let custom_emit = self.props.compile_flags.iter().any(|s| s.starts_with("--emit"));
+        let metadata_only = self.checks_metadata_only();
+
         match self.config.mode {
             CompileFail | Ui => {
                 // compile-fail and ui tests tend to have tons of unused code as
@@ -1322,11 +1983,318 @@ impl<'test> TestCx<'test> {
                 if !self.config.src_base.ends_with("rustdoc-ui") {
                     rustc.args(&["-A", "unused"]);
                 }
+                if metadata_only {
+                    rustc.args(&["--emit", "metadata"]);
+                }
+            }
+            RunPass | RunFail | RunPassValgrind => {
+                if let Some(ref sanitizer) = self.config.sanitizer {
+                    rustc.arg(format!("-Zsanitizer={}", sanitizer));
+                }
+                if self.config.deny_warnings
+                    && self.config.mode != RunFail
+                    && !self.props.allow_warnings
+                {
+                    rustc.args(&["-D", "warnings"]);
+                }
             }
             _ => {}
         }
 
-        self.compose_and_run_compiler(rustc, None)
+        // The artifacts asked for by the `emit` header and `--check-dep-info`
+        // get paths of their own, or rustc would warn that `-o` can't be used
+        // for more than one kind of output.
+        let mut emit = self
+            .props
+            .emit
+            .iter()
+            .map(|kind| format!("{}={}", kind, self.emit_path(kind).display()))
+            .collect::<Vec<_>>();
+        if self.config.check_dep_info && !self.config.src_base.ends_with("rustdoc-ui") {
+            emit.push(format!("dep-info={}", self.dep_info_path().display()));
+        }
+        if !emit.is_empty() {
+            // Any `--emit` replaces what is emitted by default, so unless
+            // something else is asked for already, ask for the usual output too.
+            if !(custom_emit || metadata_only || self.props.skip_codegen) {
+                emit.push("link".to_string());
+            }
+            rustc.args(&["--emit", &emit.join(",")]);
+        }
+
+        if self.uses_linker_map() {
+            rustc.arg(format!("-Clink-arg=/MAP:{}", self.linker_map_path().display()));
+        }
+
+        rustc
+    }
+
+    /// Whether the test is checked with `--emit=metadata` rather than built.
+    /// Most compile-fail tests only expect errors from before code generation,
+    /// so with `--compile-fail-metadata` they are.
+    fn checks_metadata_only(&self) -> bool {
+        self.config.mode == CompileFail
+            && self.config.compile_fail_metadata
+            && !self.props.needs_codegen
+            && !self.props.skip_codegen
+            && !self.props.compile_flags.iter().any(|s| s.starts_with("--emit"))
+    }
+
+    /// Compiles the test once more with `--emit=metadata` and once built, as
+    /// for `--compare-compile-fail-metadata`, noting how long each took. Fails
+    /// if building it reports errors that checking it didn't.
+    fn compare_with_full_build(&self) {
+        let full_props = TestProps {
+            needs_codegen: true,
+            ..self.props.clone()
+        };
+        let full_cx = TestCx {
+            props: &full_props,
+            ..*self
+        };
+        // Only the compiler is timed: the auxiliary crates are built already.
+        let start = Instant::now();
+        let metadata_res = self.run_compiler(self.compile_test_command(), None);
+        let metadata_time = start.elapsed();
+        let start = Instant::now();
+        let full_res = full_cx.run_compiler(full_cx.compile_test_command(), None);
+        let full_time = start.elapsed();
+        report::note_metadata_comparison(metadata_time, full_time);
+
+        let file_name = self.testpaths.file.display().to_string();
+        let errors = |proc_res: &ProcRes| {
+            json::parse_output(&file_name, &proc_res.stderr, proc_res)
+                .into_iter()
+                .filter(|e| e.kind == Some(ErrorKind::Error))
+                .map(|e| format!("{}: {}", e.line_num, e.msg))
+                .collect::<HashSet<_>>()
+        };
+        let checked = errors(&metadata_res);
+        let mut missed = errors(&full_res)
+            .into_iter()
+            .filter(|e| !checked.contains(e))
+            .collect::<Vec<_>>();
+        if !missed.is_empty() {
+            missed.sort();
+            for error in &missed {
+                println!("only reported when built: {}", error);
+            }
+            self.fatal_proc_rec(
+                "the test reports errors when built that `--emit=metadata` misses; \
+                 give it the `needs-codegen` header",
+                &full_res,
+            );
+        }
+    }
+
+    /// Whether the symbols for the `check-symbol` headers come from a map file
+    /// written by the linker. The symbol table of an MSVC binary is in its PDB,
+    /// so `dumpbin` finds next to nothing in the binary itself.
+    fn uses_linker_map(&self) -> bool {
+        self.config.target.contains("msvc")
+            && (!self.props.check_symbols.is_empty() || !self.props.check_no_symbols.is_empty())
+    }
+
+    fn linker_map_path(&self) -> PathBuf {
+        self.make_exe_name().with_extension("map")
+    }
+
+    /// Where the compiler writes the artifact of kind `kind` asked for with the
+    /// `emit` header.
+    fn emit_path(&self, kind: &str) -> PathBuf {
+        self.make_exe_name().with_extension(header::emit_extension(kind).unwrap())
+    }
+
+    fn dep_info_path(&self) -> PathBuf {
+        self.emit_path("dep-info")
+    }
+
+    /// Checks that the artifacts asked for with the `emit` header were written
+    /// and aren't empty.
+    fn check_emitted(&self, proc_res: &ProcRes) {
+        for kind in &self.props.emit {
+            let path = self.emit_path(kind);
+            match fs::metadata(&path) {
+                Ok(ref m) if m.len() > 0 => {}
+                Ok(_) => self.fatal_proc_rec(
+                    &format!("the emitted {} `{}` is empty", kind, path.display()),
+                    proc_res,
+                ),
+                Err(_) => self.fatal_proc_rec(
+                    &format!("the compiler didn't emit {} to `{}`", kind, path.display()),
+                    proc_res,
+                ),
+            }
+        }
+    }
+
+    /// Notes the size of the binary built from the test, if it built one, and
+    /// checks it against the `max-binary-size` header.
+    fn check_binary_size(&self, proc_res: &ProcRes) {
+        let binary = self.make_exe_name();
+        let size = match fs::metadata(&binary) {
+            Ok(ref m) if m.is_file() => m.len(),
+            _ => {
+                if self.props.max_binary_size.is_some() {
+                    self.fatal_proc_rec(
+                        &format!(
+                            "`max-binary-size` needs a binary, but `{}` wasn't built",
+                            binary.display()
+                        ),
+                        proc_res,
+                    );
+                }
+                return;
+            }
+        };
+        report::note_binary_size(size);
+        if let Some(limit) = self.props.max_binary_size {
+            if size > limit {
+                self.fatal_proc_rec(
+                    &format!(
+                        "`{}` is {} bytes, more than the {} bytes allowed by `max-binary-size`",
+                        binary.display(),
+                        size,
+                        limit
+                    ),
+                    proc_res,
+                );
+            }
+        }
+    }
+
+    /// Checks the symbols of the binary built from the test against its
+    /// `check-symbol` and `check-no-symbol` headers.
+    fn check_symbols(&self, proc_res: &ProcRes) {
+        let binary = self.make_exe_name();
+        if !binary.exists() {
+            self.fatal_proc_rec(
+                &format!("`check-symbol` needs a binary, but `{}` wasn't built", binary.display()),
+                proc_res,
+            );
+        }
+
+        let (source, symbols) = if self.uses_linker_map() {
+            let map = self.linker_map_path();
+            match fs::read_to_string(&map) {
+                Ok(contents) => (map.display().to_string(), linker_map_symbols(&contents)),
+                Err(e) => self.fatal_proc_rec(
+                    &format!("failed to read linker map `{}`: {}", map.display(), e),
+                    proc_res,
+                ),
+            }
+        } else {
+            let mut nm = self.command(&self.config.nm);
+            nm.arg(&binary);
+            let cmdline = nm.command_line();
+            // The symbols of a binary are many, so this doesn't go through
+            // `compose_and_run`, which keeps only so much output.
+            match nm.output() {
+                Ok(ref output) if output.status.success() => {
+                    (cmdline, nm_symbols(&String::from_utf8_lossy(&output.stdout)))
+                }
+                Ok(output) => self.fatal(&format!(
+                    "`{}` {}:\n{}",
+                    cmdline,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                )),
+                Err(e) => self.fatal(&format!("failed to run `{}`: {}", cmdline, e)),
+            }
+        };
+        let has_symbol = |symbol: &str| symbols.iter().any(|name| name.contains(symbol));
+
+        let mut mismatch = false;
+        for symbol in &self.props.check_symbols {
+            if !has_symbol(symbol) {
+                let msg = format!("`{}` has no symbol containing `{}`", binary.display(), symbol);
+                self.error(&msg);
+                mismatch = true;
+            }
+        }
+        for symbol in &self.props.check_no_symbols {
+            if has_symbol(symbol) {
+                let msg = format!("`{}` has a symbol containing `{}`", binary.display(), symbol);
+                self.error(&msg);
+                mismatch = true;
+            }
+        }
+        if mismatch {
+            println!("symbols defined according to `{}`:\n{}", source, symbols.join("\n"));
+            self.fatal("the symbols of the binary don't match the `check-symbol` headers");
+        }
+    }
+
+    /// Checks that every file the dep-info of the test lists exists and is in
+    /// the test tree, where it can't be a temporary or stale file.
+    fn check_dep_info(&self, proc_res: &ProcRes) {
+        let dep_info = self.dep_info_path();
+        let contents = match fs::read_to_string(&dep_info) {
+            Ok(contents) => contents,
+            Err(e) => self.fatal_proc_rec(
+                &format!("failed to read dep-info `{}`: {}", dep_info.display(), e),
+                proc_res,
+            ),
+        };
+        let test_tree = fs::canonicalize(self.config.src_base.parent().unwrap()).unwrap();
+        for line in contents.lines() {
+            // Lines look like `target: dep dep ...`, with spaces in paths
+            // escaped with `\`.
+            let deps = match line.find(": ") {
+                Some(i) => &line[i + 2..],
+                None => continue,
+            };
+            let deps = deps.replace("\\ ", "\0");
+            for dep in deps.split_whitespace() {
+                let dep = dep.replace('\0', " ");
+                let path = match fs::canonicalize(&dep) {
+                    Ok(path) => path,
+                    Err(_) => self.fatal_proc_rec(
+                        &format!("dep-info lists `{}`, which doesn't exist", dep),
+                        proc_res,
+                    ),
+                };
+                if !path.starts_with(&test_tree) {
+                    self.fatal_proc_rec(
+                        &format!("dep-info lists `{}`, which is outside of the test tree", dep),
+                        proc_res,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Compiles the test `runs` times with `-Z time-passes` instead of testing
+    /// it, noting the time each compilation took and the time the compiler
+    /// says it spent in each of its passes.
+    fn run_compile_bench(&self, runs: usize) {
+        self.build_all_auxiliary();
+        self.build_native_libs();
+        for _ in 0..runs {
+            let mut rustc = self.compile_test_command();
+            rustc.args(&["-Z", "time-passes"]);
+            let start = Instant::now();
+            let proc_res = self.run_compiler(rustc, None);
+            let wall_time = start.elapsed();
+            self.check_no_compiler_crash(&proc_res);
+
+            // Lines look like `time: 0.012; rss: 48MB\tparsing`, with those of
+            // nested passes indented. Only the top-level passes are kept, so
+            // that nothing is counted twice.
+            let passes = proc_res
+                .stdout
+                .lines()
+                .chain(proc_res.stderr.lines())
+                .filter(|line| line.starts_with("time:"))
+                .filter_map(|line| {
+                    let mut parts = line["time:".len()..].splitn(2, '\t');
+                    let secs = parts.next()?.split(';').next()?.trim().parse::<f64>().ok()?;
+                    let pass = parts.next()?.trim().to_string();
+                    Some((pass, Duration::from_millis((secs * 1000.0) as u64)))
+                })
+                .collect();
+            report::note_compile_sample(wall_time, passes);
+        }
     }
 
     fn document(&self, out_dir: &Path) -> ProcRes {
@@ -1358,7 +2326,7 @@ impl<'test> TestCx<'test> {
             .rustdoc_path
             .as_ref()
             .expect("--rustdoc-path passed");
-        let mut rustdoc = Command::new(rustdoc_path);
+        let mut rustdoc = self.command(rustdoc_path);
 
         rustdoc
             .arg("-L")
@@ -1382,7 +2350,9 @@ impl<'test> TestCx<'test> {
     }
 
     fn exec_compiled_test(&self) -> ProcRes {
+        let start = Instant::now();
         let env = &self.props.exec_env;
+        let input = self.read_run_input();
 
         let proc_res = match &*self.config.target {
             // This is pretty similar to below, we're transforming:
@@ -1412,7 +2382,7 @@ impl<'test> TestCx<'test> {
                     }
                 }
                 let mut test_client =
-                    Command::new(self.config.remote_test_client.as_ref().unwrap());
+                    self.command(self.config.remote_test_client.as_ref().unwrap());
                 test_client
                     .args(&["run", &prog])
                     .args(args)
@@ -1427,29 +2397,104 @@ impl<'test> TestCx<'test> {
             _ => {
                 let aux_dir = self.aux_output_dir_name();
                 let ProcArgs { prog, args } = self.make_run_args();
-                let mut program = Command::new(&prog);
+                if self.config.debug_test.is_some() {
+                    self.write_debug_env(&prog, &args, aux_dir.to_str().unwrap());
+                }
+                let mut program = if self.config.debug_test_pause {
+                    self.paused_command(&prog)
+                } else {
+                    self.command(&prog)
+                };
                 program
                     .args(args)
                     .current_dir(&self.output_base_dir())
+                    .env(SCRATCH_DIR_ENV, self.make_scratch_dir())
                     .envs(env.clone());
                 self.compose_and_run(
                     program,
                     self.config.run_lib_path.to_str().unwrap(),
                     Some(aux_dir.to_str().unwrap()),
-                    None,
+                    input,
                 )
             }
         };
 
-        if proc_res.status.success() {
+        if proc_res.status.success() && self.config.keep_artifacts != KeepArtifacts::All {
             // delete the executable after running it to save space.
             // it is ok if the deletion failed.
             let _ = fs::remove_file(self.make_exe_name());
         }
 
+        report::note_run_time(start.elapsed());
         proc_res
     }
 
+    /// Writes an `env.sh` to the output directory of the test that sets up the
+    /// environment its binary runs in, for running it by hand with `--debug-test`.
+    fn write_debug_env(&self, prog: &str, args: &[String], aux_dir: &str) {
+        let lib_path = self.config.run_lib_path.to_str().unwrap();
+        let mut vars = vec![
+            (
+                dylib_env_var().to_string(),
+                dylib_search_path(lib_path, Some(aux_dir)).to_string_lossy().into_owned(),
+            ),
+            (
+                SCRATCH_DIR_ENV.to_string(),
+                self.make_scratch_dir().to_str().unwrap().to_string(),
+            ),
+        ];
+        vars.extend(self.props.exec_env.iter().cloned());
+
+        let mut script = String::from("# Sourced, this sets up the environment of\n#\n#    ");
+        let command = Some(prog.to_string()).into_iter().chain(args.iter().cloned());
+        script.push_str(&command.map(|a| util::quote_arg(&a)).collect::<Vec<_>>().join(" "));
+        script.push_str("\n\n");
+        let dir = self.output_base_dir();
+        script.push_str(&format!("cd {}\n", util::quote_arg(dir.to_str().unwrap())));
+        for (key, value) in vars {
+            script.push_str(&format!("export {}={}\n", key, util::quote_arg(&value)));
+        }
+
+        let path = dir.join("env.sh");
+        fs::write(&path, script).unwrap();
+        println!("environment of the test binary written to {}", path.display());
+    }
+
+    /// A command running `prog` that stops before it starts and says how to
+    /// attach a debugger to it and let it continue.
+    #[cfg(unix)]
+    fn paused_command(&self, prog: &str) -> Command {
+        // The shell stops itself and then becomes the program, keeping the
+        // process ID a debugger attaches to. Stdout is piped to compiletest.
+        let script = "echo \"stopped $0 (pid $$); attach with \\`gdb -p $$\\` or \
+                      \\`lldb -p $$\\`, then \\`kill -CONT $$\\`\" >/dev/tty; \
+                      kill -STOP $$; exec \"$0\" \"$@\"";
+        let mut command = self.command("sh");
+        command.args(&["-c", script, prog]);
+        command
+    }
+
+    /// A command running `prog`, once a debugger is ready to attach to it.
+    #[cfg(not(unix))]
+    fn paused_command(&self, prog: &str) -> Command {
+        // There's no stopping a process before it starts, so wait for the
+        // debugger to be started instead.
+        println!("press Enter to run {}, then attach a debugger to it", prog);
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).unwrap();
+        self.command(prog)
+    }
+
+    /// The contents of the file named by the `run-input` header, if any.
+    fn read_run_input(&self) -> Option<String> {
+        let file = self.props.run_input.as_ref()?;
+        let path = self.testpaths.file.parent().unwrap().join(file);
+        match fs::read_to_string(&path) {
+            Ok(input) => Some(input),
+            Err(e) => self.fatal(&format!("failed to read {}: {}", path.display(), e)),
+        }
+    }
+
     /// For each `aux-build: foo/bar` annotation, we check to find the
     /// file in a `auxiliary` directory relative to the test itself.
     fn compute_aux_test_paths(&self, rel_ab: &str) -> TestPaths {
@@ -1481,19 +2526,47 @@ impl<'test> TestCx<'test> {
         }
     }
 
-    fn compose_and_run_compiler(&self, mut rustc: Command, input: Option<String>) -> ProcRes {
+    fn compose_and_run_compiler(&self, rustc: Command, input: Option<String>) -> ProcRes {
+        let start = Instant::now();
+        self.build_all_auxiliary();
+        self.build_native_libs();
+        let proc_res = self.run_compiler(rustc, input);
+        report::note_compile_time(start.elapsed());
+        proc_res
+    }
+
+    /// Builds the auxiliary crates of the test into its auxiliary directory.
+    fn build_all_auxiliary(&self) {
         let aux_dir = self.aux_output_dir_name();
 
-        if !self.props.aux_builds.is_empty() {
+        // Crates built for the host get a fixed crate type, the others are
+        // built as libraries of the kind the target supports.
+        let aux_builds = self
+            .props
+            .aux_builds
+            .iter()
+            .map(|ab| (ab, None))
+            .chain(self.props.aux_proc_macros.iter().map(|ab| (ab, Some("proc-macro"))))
+            .chain(self.props.aux_plugins.iter().map(|ab| (ab, Some("dylib"))))
+            .collect::<Vec<_>>();
+
+        if !aux_builds.is_empty() {
             let _ = fs::remove_dir_all(&aux_dir);
             create_dir_all(&aux_dir).unwrap();
         }
 
-        for rel_ab in &self.props.aux_builds {
+        // The keys of the auxiliary crates built so far, as long as all of them
+        // could be shared.
+        let mut shared = Some(Vec::new());
+
+        for (rel_ab, host_crate_type) in aux_builds {
             let aux_testpaths = self.compute_aux_test_paths(rel_ab);
-            let aux_props =
+            let mut aux_props =
                 self.props
                     .from_aux_file(&aux_testpaths.file, self.revision, self.config);
+            if host_crate_type.is_some() {
+                aux_props.force_host = true;
+            }
             let aux_output = TargetLocation::ThisDirectory(self.aux_output_dir_name());
             let aux_cx = TestCx {
                 config: self.config,
@@ -1505,7 +2578,9 @@ impl<'test> TestCx<'test> {
             create_dir_all(aux_cx.output_base_dir()).unwrap();
             let mut aux_rustc = aux_cx.make_compile_args(&aux_testpaths.file, aux_output);
 
-            let crate_type = if aux_props.no_prefer_dynamic {
+            let crate_type = if host_crate_type.is_some() {
+                host_crate_type
+            } else if aux_props.no_prefer_dynamic {
                 None
             } else if self.config.target.contains("cloudabi")
                 || self.config.target.contains("emscripten")
@@ -1531,7 +2606,29 @@ impl<'test> TestCx<'test> {
             }
 
             aux_rustc.arg("-L").arg(&aux_dir);
+            aux_rustc.envs(aux_props.rustc_env.clone());
+
+            // Crates with native libraries of their own are always built.
+            let key = if self.config.share_aux_builds && aux_props.aux_native_libs.is_empty() {
+                shared.as_ref().map(|deps| {
+                    aux_cache::key(self.config, &aux_rustc, &aux_dir, &aux_testpaths.file, deps)
+                })
+            } else {
+                None
+            };
+            let lock = key.as_ref().map(|key| aux_cache::lock(key));
+            let _guard = lock
+                .as_ref()
+                .map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+            if let Some(ref key) = key {
+                if aux_cache::fetch(self.config, key, &aux_dir) {
+                    shared.as_mut().unwrap().push(key.clone());
+                    continue;
+                }
+            }
+            let before = aux_cache::files(&aux_dir);
 
+            aux_cx.build_native_libs();
             let auxres = aux_cx.compose_and_run(
                 aux_rustc,
                 aux_cx.config.compile_lib_path.to_str().unwrap(),
@@ -1547,13 +2644,24 @@ impl<'test> TestCx<'test> {
                     &auxres,
                 );
             }
+            match key {
+                Some(key) => {
+                    aux_cache::store(self.config, &key, &aux_dir, &before);
+                    shared.as_mut().unwrap().push(key);
+                }
+                None => shared = None,
+            }
         }
+    }
 
+    /// Runs the compiler on the test, once its auxiliary crates are built.
+    fn run_compiler(&self, mut rustc: Command, input: Option<String>) -> ProcRes {
+        rustc.env(SCRATCH_DIR_ENV, self.make_scratch_dir());
         rustc.envs(self.props.rustc_env.clone());
         self.compose_and_run(
             rustc,
             self.config.compile_lib_path.to_str().unwrap(),
-            Some(aux_dir.to_str().unwrap()),
+            Some(self.aux_output_dir_name().to_str().unwrap()),
             input,
         )
     }
@@ -1567,31 +2675,51 @@ impl<'test> TestCx<'test> {
     ) -> ProcRes {
         let cmdline = {
             let cmdline = self.make_cmdline(&command, lib_path);
-            logv(self.config, format!("executing {}", cmdline));
+            info!("executing {}", cmdline);
+            if self.config.debug_test.is_some() {
+                println!("executing {}", cmdline);
+            }
             cmdline
         };
+        report::note_command(&cmdline);
 
         command
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .stdin(Stdio::piped());
 
-        // Need to be sure to put both the lib_path and the aux path in the dylib
-        // search path for the child.
-        let mut path = env::split_paths(&env::var_os(dylib_env_var()).unwrap_or(OsString::new()))
-            .collect::<Vec<_>>();
-        if let Some(p) = aux_path {
-            path.insert(0, PathBuf::from(p))
-        }
-        path.insert(0, PathBuf::from(lib_path));
-
         // Add the new dylib search path var
-        let newpath = env::join_paths(&path).unwrap();
+        let newpath = dylib_search_path(lib_path, aux_path);
+        let dylib_path = newpath.to_string_lossy().into_owned();
         command.env(dylib_env_var(), newpath);
 
-        let mut child = disable_error_reporting(|| command.spawn())
-            .expect(&format!("failed to exec `{:?}`", &command));
-        if let Some(input) = input {
+        if record::replaying() {
+            let interaction = record::replay(&cmdline);
+            let result = ProcRes {
+                status: interaction.status(),
+                stdout: interaction.stdout,
+                stderr: interaction.stderr,
+                cmdline,
+            };
+            result.note();
+            self.dump_output(result.stdout.as_bytes(), result.stderr.as_bytes());
+            return result;
+        }
+
+        // A test expecting its program to be killed by a signal gets no dump.
+        let collect_dump = self.config.collect_core_dumps && self.props.failure_signal.is_none();
+        if collect_dump {
+            core_dump::enable(&mut command);
+        }
+        let spawned = if collect_dump && cfg!(windows) {
+            // Windows Error Reporting writes the minidumps.
+            job::spawn(&mut command)
+        } else {
+            disable_error_reporting(|| job::spawn(&mut command))
+        };
+        let (mut child, _group) = spawned.expect(&format!("failed to exec `{:?}`", &command));
+        let pid = child.id();
+        if let Some(ref input) = input {
             child
                 .stdin
                 .as_mut()
@@ -1600,11 +2728,40 @@ impl<'test> TestCx<'test> {
                 .unwrap();
         }
 
-        let Output {
-            status,
-            stdout,
-            stderr,
-        } = read2_abbreviated(child).expect("failed to read output");
+        let timeout = if self.config.debug_test.is_some() {
+            None
+        } else {
+            self.props.timeout.or(self.config.timeout)
+        };
+        let watchdog = timeout.map(|secs| spawn_watchdog(pid, Duration::from_secs(secs)));
+        let spill = if self.config.spill_output {
+            let (out_ext, err_ext) = self.output_extensions();
+            Some((self.create_output_file(&out_ext), self.create_output_file(&err_ext)))
+        } else {
+            None
+        };
+
+        let (
+            Output {
+                status,
+                stdout,
+                stderr,
+            },
+            max_rss,
+        ) = read2_abbreviated_with_max_rss(
+            child,
+            self.config.output_limit,
+            self.stream_prefix(),
+            spill,
+        ).expect("failed to read output");
+
+        let timed_out = match watchdog {
+            Some((disarm, handle)) => {
+                drop(disarm);
+                handle.join().unwrap()
+            }
+            None => false,
+        };
 
         let result = ProcRes {
             status,
@@ -1613,17 +2770,75 @@ impl<'test> TestCx<'test> {
             cmdline,
         };
 
-        self.dump_output(&result.stdout, &result.stderr);
+        record::record(&record::Interaction::new(
+            &result.cmdline,
+            &dylib_path,
+            input.as_ref().map(|s| &s[..]),
+            &result.status,
+            &result.stdout,
+            &result.stderr,
+        ));
+        result.note();
+        if self.config.spill_output {
+            self.note_output(&stdout, &stderr);
+        } else {
+            self.dump_output(&stdout, &stderr);
+        }
+        if collect_dump && !timed_out && core_dump::crashed(&result.status) {
+            self.collect_core_dump(pid, &command.get_program().to_string_lossy());
+        }
+
+        if timed_out {
+            self.fatal_proc_rec(
+                &format!("timed out after {} seconds", timeout.unwrap()),
+                &result,
+            );
+        }
+
+        if let Some(max_rss) = max_rss {
+            report::note_max_rss(max_rss);
+            if let Some(limit) = self.props.max_rss {
+                if max_rss > limit * 1024 * 1024 {
+                    self.fatal_proc_rec(
+                        &format!(
+                            "used {} MiB of memory, more than the {} MiB allowed by `max-rss`",
+                            max_rss / (1024 * 1024),
+                            limit
+                        ),
+                        &result,
+                    );
+                }
+            }
+        }
 
         result
     }
 
+    /// A command for `program` that doesn't inherit our environment, but for
+    /// the variables in `ENV_ALLOWLIST` and those passed with `--pass-env`.
+    /// That way, something like `RUSTFLAGS` or `RUST_LOG` set in the shell
+    /// the tests were started from can't change how they behave.
+    fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        let mut command = Command::new(program);
+        command.env_clear();
+        for (key, value) in env::vars_os() {
+            let passed = key.to_str().map_or(false, |key| {
+                ENV_ALLOWLIST.iter().any(|allowed| env_name_eq(allowed, key))
+                    || self.config.pass_env.iter().any(|allowed| env_name_eq(allowed, key))
+            });
+            if passed {
+                command.env(key, value);
+            }
+        }
+        command
+    }
+
     fn make_compile_args(&self, input_file: &Path, output_file: TargetLocation) -> Command {
         let is_rustdoc = self.config.src_base.ends_with("rustdoc-ui");
         let mut rustc = if !is_rustdoc {
-            Command::new(&self.config.rustc_path)
+            self.command(&self.config.rustc_path)
         } else {
-            Command::new(
+            self.command(
                 &self
                     .config
                     .rustdoc_path
@@ -1709,7 +2924,8 @@ impl<'test> TestCx<'test> {
                 rustc.arg(dir_opt);
             }
             RunPass | RunFail | RunPassValgrind | Pretty | DebugInfoGdb | DebugInfoLldb
-            | Codegen | Rustdoc | RunMake | CodegenUnits => {
+            | Codegen | Rustdoc | RunMake | CodegenUnits | Assembly | CoverageMap
+            | CoverageRun | DocExamples => {
                 // do not use JSON output
             }
         }
@@ -1761,6 +2977,16 @@ impl<'test> TestCx<'test> {
             if let Some(ref linker) = self.config.linker {
                 rustc.arg(format!("-Clinker={}", linker));
             }
+
+            if !self.props.aux_native_libs.is_empty() {
+                rustc.arg("-L").arg(format!("native={}", self.native_lib_dir().display()));
+            }
+            for lib in &self.props.aux_native_libs {
+                rustc.arg("-l").arg(format!("static={}", native_lib_name(lib)));
+            }
+            for flag in &self.props.link_flags {
+                rustc.arg(format!("-Clink-arg={}", flag));
+            }
         }
 
         rustc.args(&self.props.compile_flags);
@@ -1768,6 +2994,68 @@ impl<'test> TestCx<'test> {
         rustc
     }
 
+    /// Directory the libraries of `aux-native-lib` are built in. Example:
+    ///   /.../testname.revision.mode/native/
+    fn native_lib_dir(&self) -> PathBuf {
+        self.output_base_dir().join("native")
+    }
+
+    /// Builds each C file named by an `aux-native-lib` header into a static
+    /// library, using the C compiler and archiver passed to compiletest.
+    fn build_native_libs(&self) {
+        if self.props.aux_native_libs.is_empty() {
+            return;
+        }
+        if self.config.cc.is_empty() {
+            self.fatal("`aux-native-lib` needs a C compiler, but `--cc` is empty");
+        }
+        let out_dir = self.native_lib_dir();
+        create_dir_all(&out_dir).unwrap();
+        let src_dir = self.testpaths.file.parent().unwrap();
+        let msvc = self.config.target.contains("msvc");
+
+        for lib in &self.props.aux_native_libs {
+            let name = native_lib_name(lib);
+            let src = src_dir.join(lib);
+            let obj = out_dir.join(format!("{}.{}", name, if msvc { "obj" } else { "o" }));
+
+            let mut cc = self.command(&self.config.cc);
+            cc.args(self.config.cflags.split_whitespace());
+            if msvc {
+                cc.args(&["/nologo", "/c"]).arg(&src).arg(format!("/Fo{}", obj.display()));
+            } else {
+                cc.arg("-c").arg(&src).arg("-o").arg(&obj);
+            }
+            self.run_native_lib_step(cc, lib);
+
+            let mut ar = if msvc {
+                let mut lib_exe = self.command("lib");
+                lib_exe
+                    .arg("/nologo")
+                    .arg(format!("/OUT:{}", out_dir.join(format!("{}.lib", name)).display()));
+                lib_exe
+            } else {
+                let mut ar = self.command(&self.config.ar);
+                ar.arg("crs").arg(out_dir.join(format!("lib{}.a", name)));
+                ar
+            };
+            ar.arg(&obj);
+            self.run_native_lib_step(ar, lib);
+        }
+    }
+
+    fn run_native_lib_step(&self, cmd: Command, lib: &str) {
+        let proc_res = self.compose_and_run(
+            cmd,
+            self.config.compile_lib_path.to_str().unwrap(),
+            None,
+            None,
+        );
+        if !proc_res.status.success() {
+            self.fatal_proc_rec(&format!("failed to build native library `{}`", lib), &proc_res);
+        }
+    }
+
     fn make_exe_name(&self) -> PathBuf {
         // Using a single letter here to keep the path length down for
         // Windows.  Some test names get very long.  rustc creates `rcgu`
@@ -1788,7 +3076,25 @@ impl<'test> TestCx<'test> {
     fn make_run_args(&self) -> ProcArgs {
         // If we've got another tool to run under (valgrind),
         // then split apart its command
-        let mut args = self.split_maybe_args(&self.config.runtool);
+        let mut args = self.split_maybe_args(&self.runtool());
+
+        // Have valgrind report errors with an exit code the test can't be
+        // confused with, and keep its messages out of the test's output.
+        if self.runs_under_valgrind() {
+            args.insert(1, format!("--error-exitcode={}", self.config.valgrind_error_code));
+            args.insert(2, format!("--log-file={}", self.valgrind_log_path().display()));
+        }
+
+        // If we're emulating the target, run the test under QEMU in user mode.
+        // The emulated program inherits our environment, including the
+        // dynamic library search path set up in `compose_and_run`.
+        if let Some(ref qemu) = self.config.qemu {
+            args.push(qemu.clone());
+            if let Some(ref sysroot) = self.config.qemu_sysroot {
+                args.push("-L".to_owned());
+                args.push(sysroot.to_str().unwrap().to_owned());
+            }
+        }
 
         // If this is emscripten, then run tests under nodejs
         if self.config.target.contains("emscripten") {
@@ -1799,9 +3105,11 @@ impl<'test> TestCx<'test> {
             }
         }
 
-        // If this is otherwise wasm , then run tests under nodejs with our
-        // shim
-        if self.config.target.contains("wasm32") {
+        // If this is otherwise wasm, then run tests under the configured
+        // runtime, or under nodejs with our shim
+        if self.config.target.contains("wasm32") && self.config.wasm_runtime.is_some() {
+            args.push(self.config.wasm_runtime.clone().unwrap());
+        } else if self.config.target.contains("wasm32") {
             if let Some(ref p) = self.config.nodejs {
                 args.push(p.clone());
             } else {
@@ -1848,40 +3156,77 @@ impl<'test> TestCx<'test> {
 
         // Linux and mac don't require adjusting the library search path
         if cfg!(unix) {
-            format!("{:?}", command)
+            command.command_line()
         } else {
-            // Build the LD_LIBRARY_PATH variable as it would be seen on the command line
-            // for diagnostic purposes
-            fn lib_path_cmd_prefix(path: &str) -> String {
-                format!(
-                    "{}=\"{}\"",
-                    util::lib_path_env_var(),
-                    util::make_new_path(path)
-                )
-            }
+            // Show how PATH would be set in cmd.exe, for diagnostic purposes
+            format!(
+                "set \"{}={}\" && {}",
+                util::lib_path_env_var(),
+                util::make_new_path(libpath),
+                command.command_line()
+            )
+        }
+    }
 
-            format!("{} {:?}", lib_path_cmd_prefix(libpath), command)
+    /// Moves the dump of the crashed process `pid`, which ran `program`, next
+    /// to the output of the test, and says where it is.
+    fn collect_core_dump(&self, pid: u32, program: &str) {
+        let dest = self.make_out_name(&match self.revision {
+            Some(revision) => format!("{}.{}", revision, core_dump::extension()),
+            None => core_dump::extension().to_string(),
+        });
+        let output_dir = self.output_base_dir();
+        let cwd = env::current_dir().unwrap();
+        if core_dump::collect(pid, program, &[&output_dir, &cwd], &dest) {
+            println!("core dump of `{}` written to {}", program, dest.display());
+            report::note_core_dump(&dest);
+        } else {
+            println!("no core dump of `{}` (pid {}) found", program, pid);
         }
     }
 
-    fn dump_output(&self, out: &str, err: &str) {
+    /// Writes the output of a process to the `.out` and `.err` files of the
+    /// test as is, whether or not it's valid UTF-8.
+    fn dump_output(&self, out: &[u8], err: &[u8]) {
+        let (out_ext, err_ext) = self.output_extensions();
+        self.dump_output_file(out, &out_ext);
+        self.dump_output_file(err, &err_ext);
+        self.note_output(out, err);
+    }
+
+    /// Tells the report where the output of a process was dumped to, and
+    /// prints it if we're very verbose.
+    fn note_output(&self, out: &[u8], err: &[u8]) {
+        let (out_ext, err_ext) = self.output_extensions();
+        report::note_output(&self.make_out_name(&out_ext), &self.make_out_name(&err_ext));
+        self.maybe_dump_to_stdout(out, err);
+    }
+
+    /// The extensions of the files the stdout and stderr of processes are
+    /// dumped to.
+    fn output_extensions(&self) -> (String, String) {
         let revision = if let Some(r) = self.revision {
             format!("{}.", r)
         } else {
             String::new()
         };
+        (format!("{}out", revision), format!("{}err", revision))
+    }
 
-        self.dump_output_file(out, &format!("{}out", revision));
-        self.dump_output_file(err, &format!("{}err", revision));
-        self.maybe_dump_to_stdout(out, err);
+    fn dump_output_file<C: AsRef<[u8]>>(&self, out: C, extension: &str) {
+        if let Err(e) = self.create_output_file(extension).write_all(out.as_ref()) {
+            panic!("failed to write output to {}: {}", self.make_out_name(extension).display(), e);
+        }
     }
 
-    fn dump_output_file(&self, out: &str, extension: &str) {
+    fn create_output_file(&self, extension: &str) -> File {
         let outfile = self.make_out_name(extension);
+        // Don't rely on the output directory having been made already.
+        if let Some(dir) = outfile.parent() {
+            create_dir_all(dir).unwrap();
+        }
         File::create(&outfile)
-            .unwrap()
-            .write_all(out.as_bytes())
-            .unwrap();
+            .unwrap_or_else(|e| panic!("failed to create {}: {}", outfile.display(), e))
     }
 
     /// Create a filename for output with the given extension.  Example:
@@ -1920,6 +3265,34 @@ impl<'test> TestCx<'test> {
         output_base_dir(self.config, self.testpaths, self.safe_revision())
     }
 
+    /// A directory of the test's own for temporary files, which is passed to
+    /// the compiler and the test program in `SCRATCH_DIR_ENV`. Example:
+    ///   /.../testname.revision.mode/scratch/
+    fn scratch_dir(&self) -> PathBuf {
+        self.output_base_dir().join("scratch")
+    }
+
+    /// What to prefix the output of processes with when `--stream-output`
+    /// prints it as it comes, or `None` without that flag.
+    fn stream_prefix(&self) -> Option<String> {
+        if !self.config.stream_output {
+            return None;
+        }
+        let file_name = self.testpaths.file.file_name().unwrap();
+        let name = self.testpaths.relative_dir.join(file_name);
+        Some(match self.revision {
+            Some(rev) => format!("{}#{}", name.display(), rev),
+            None => name.display().to_string(),
+        })
+    }
+
+    /// Creates the scratch directory if needed and returns its path.
+    fn make_scratch_dir(&self) -> PathBuf {
+        let dir = self.scratch_dir();
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
     /// Absolute path to the base filename used as output for the given
     /// test/revision.  Example:
     ///   /.../relative/testname.revision.mode/testname
@@ -1927,34 +3300,56 @@ impl<'test> TestCx<'test> {
         output_base_name(self.config, self.testpaths, self.safe_revision())
     }
 
-    fn maybe_dump_to_stdout(&self, out: &str, err: &str) {
-        if self.config.verbose {
-            println!("------{}------------------------------", "stdout");
-            println!("{}", out);
-            println!("------{}------------------------------", "stderr");
-            println!("{}", err);
-            println!("------------------------------------------");
+    fn maybe_dump_to_stdout(&self, out: &[u8], err: &[u8]) {
+        if self.config.is_very_verbose() {
+            let out = String::from_utf8_lossy(out);
+            let err = String::from_utf8_lossy(err);
+            // Print the whole block with a single call, so that output of tests
+            // running concurrently with `--nocapture` doesn't end up interleaved.
+            print!(
+                "------{}------------------------------\n{}\n\
+                 ------{}------------------------------\n{}\n\
+                 ------------------------------------------\n",
+                "stdout", out, "stderr", err
+            );
         }
     }
 
     fn error(&self, err: &str) {
-        match self.revision {
-            Some(rev) => println!("\nerror in revision `{}`: {}", rev, err),
-            None => println!("\nerror: {}", err),
-        }
+        let prefix = match self.revision {
+            Some(rev) => format!("error in revision `{}`", rev),
+            None => "error".to_string(),
+        };
+        println!("\n{}: {}", util::paint(self.config, Color::Red, &prefix), err);
+        report::note_failure(&format!("{}: {}", prefix, err));
     }
 
     fn fatal(&self, err: &str) -> ! {
         self.error(err);
+        self.note_known_bug();
         panic!();
     }
 
     fn fatal_proc_rec(&self, err: &str, proc_res: &ProcRes) -> ! {
         self.try_print_open_handles();
         self.error(err);
+        self.note_known_bug();
         proc_res.fatal(None);
     }
 
+    /// Points out that a failing `known-bug` test may just mean that the bug
+    /// it records got fixed.
+    fn note_known_bug(&self) {
+        if let Some(ref issue) = self.props.known_bug {
+            println!(
+                "note: this test records the behavior of known bug {issue}, which has changed; \
+                 if {issue} got fixed, remove the `known-bug` directive, turn this into a \
+                 regular test and close the issue",
+                issue = issue
+            );
+        }
+    }
+
     // This function is a poor man's attempt to debug rust-lang/rust#38620, if
     // that's closed then this should be deleted
     //
@@ -1973,13 +3368,14 @@ impl<'test> TestCx<'test> {
             None => return,
         };
 
-        let mut cmd = Command::new("handle.exe");
+        let mut cmd = self.command("handle.exe");
         cmd.arg("-a").arg("-u");
         cmd.arg(filename);
         cmd.arg("-nobanner");
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
-        let output = match cmd.spawn().and_then(read2_abbreviated) {
+        let limit = self.config.output_limit;
+        let output = match cmd.spawn().and_then(|child| read2_abbreviated(child, limit)) {
             Ok(output) => output,
             Err(_) => return,
         };
@@ -2006,29 +3402,199 @@ impl<'test> TestCx<'test> {
         self.compose_and_run_compiler(rustc, None)
     }
 
-    fn check_ir_with_filecheck(&self) -> ProcRes {
-        let irfile = self.output_base_name().with_extension("ll");
-        let mut filecheck = Command::new(self.config.llvm_filecheck.as_ref().unwrap());
+    fn run_codegen_test(&self) {
+        assert!(self.revision.is_none(), "revisions not relevant here");
+
+        if self.config.llvm_filecheck.is_none() {
+            self.fatal("missing --llvm-filecheck");
+        }
+
+        let mut proc_res = self.compile_test_and_save_ir();
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("compilation failed!", &proc_res);
+        }
+
+        let output_path = self.output_base_name().with_extension("ll");
+        proc_res = self.verify_with_filecheck(&output_path);
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("verification with 'FileCheck' failed", &proc_res);
+        }
+    }
+
+    fn compile_test_and_save_assembly(&self) -> (ProcRes, PathBuf) {
+        // With only `--emit=asm` requested, rustc writes the assembly to the
+        // path passed with `-o`.
+        let output_path = self.output_base_name().with_extension("s");
+
+        let output_file = TargetLocation::ThisFile(output_path.clone());
+        let mut rustc = self.make_compile_args(&self.testpaths.file, output_file);
+        rustc.arg("-L").arg(self.aux_output_dir_name());
+
+        match self.props.assembly_output.as_ref().map(AsRef::as_ref) {
+            Some("emit-asm") => {
+                rustc.arg("--emit=asm");
+            }
+            Some(other) => {
+                self.fatal(&format!("unknown 'assembly-output' header: {}", other));
+            }
+            None => self.fatal("missing 'assembly-output' header"),
+        }
+
+        (self.compose_and_run_compiler(rustc, None), output_path)
+    }
+
+    fn verify_with_filecheck(&self, output: &Path) -> ProcRes {
+        let mut filecheck = self.command(self.config.llvm_filecheck.as_ref().unwrap());
         filecheck
             .arg("--input-file")
-            .arg(irfile)
+            .arg(output)
             .arg(&self.testpaths.file);
         self.compose_and_run(filecheck, "", None, None)
     }
 
-    fn run_codegen_test(&self) {
-        assert!(self.revision.is_none(), "revisions not relevant here");
+    fn run_coverage_test(&self) {
+        let profile_dir = self.output_base_dir().join("profiles");
+        let ir = self.make_exe_name().with_extension("ll");
+        let mut rustc = self.make_compile_args(
+            &self.testpaths.file,
+            TargetLocation::ThisFile(self.make_exe_name()),
+        );
+        rustc.arg("-L").arg(self.aux_output_dir_name());
+        // Instrument every function and inline none, so that the report
+        // follows the source.
+        rustc.arg(format!("-Cprofile-generate={}", profile_dir.display()));
+        rustc.args(&["-C", "opt-level=0", "-C", "codegen-units=1", "-C", "link-dead-code"]);
+        rustc.arg(format!("--emit=link,llvm-ir={}", ir.display()));
+        let proc_res = self.compose_and_run_compiler(rustc, None);
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("compilation failed!", &proc_res);
+        }
+
+        let krate = self
+            .testpaths
+            .file
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .replace('-', "_");
+        let (kind, report) = if self.config.mode == CoverageRun {
+            // Profiles of earlier runs would be merged into this one.
+            let _ = fs::remove_dir_all(&profile_dir);
+            let proc_res = self.exec_compiled_test();
+            if !proc_res.status.success() {
+                self.fatal_proc_rec("test run failed!", &proc_res);
+            }
+            (COVERAGE_RUN, coverage::run_report(&self.show_profile(&profile_dir), &krate))
+        } else {
+            let ir = fs::read_to_string(&ir).unwrap_or_else(|e| {
+                self.fatal(&format!("failed to read `{}`: {}", ir.display(), e))
+            });
+            (COVERAGE_MAP, coverage::map_report(&ir, &krate))
+        };
+
+        let expected = self.load_expected_output(kind);
+        if self.compare_output(kind, &report, &expected) > 0 {
+            println!("To update references, rerun the tests and pass the `--bless` flag");
+            self.fatal("the coverage report differs from the expected one");
+        }
+    }
+
+    /// The counters in the raw profiles the test wrote to `profile_dir`, merged
+    /// and printed by `llvm-profdata`.
+    fn show_profile(&self, profile_dir: &Path) -> String {
+        let llvm_profdata = self.config.llvm_tool("llvm-profdata").unwrap();
+        let profdata = self.make_exe_name().with_extension("profdata");
+        let mut merge = self.command(&llvm_profdata);
+        merge.arg("merge").arg("-o").arg(&profdata);
+        match profile_dir.read_dir() {
+            Ok(entries) => {
+                merge.args(entries.map(|entry| entry.unwrap().path()));
+            }
+            Err(e) => self.fatal(&format!("the test wrote no profile: {}", e)),
+        }
+        let proc_res = self.compose_and_run(merge, "", None, None);
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("llvm-profdata merge failed", &proc_res);
+        }
+
+        let mut show = self.command(&llvm_profdata);
+        show.args(&["show", "--all-functions", "--counts"]).arg(&profdata);
+        let proc_res = self.compose_and_run(show, "", None, None);
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("llvm-profdata show failed", &proc_res);
+        }
+        proc_res.stdout
+    }
 
+    /// Builds and runs every Rust code block in the Markdown file under test,
+    /// each as a test of its own in the output directory.
+    fn run_doc_examples_test(&self) {
+        let markdown = fs::read_to_string(&self.testpaths.file).unwrap();
+        let stem = self.testpaths.file.file_stem().unwrap().to_str().unwrap();
+        let relative_dir = self.testpaths.relative_dir.join(stem);
+        for example in examples::extract(&markdown) {
+            let file = self.output_base_dir().join(format!("{}.line{}.rs", stem, example.line));
+            fs::write(&file, example.program()).unwrap();
+            let testpaths = TestPaths {
+                file,
+                relative_dir: relative_dir.clone(),
+            };
+            let mut props = self.props.clone();
+            if let Some(ref edition) = example.edition {
+                props.compile_flags.push(format!("--edition={}", edition));
+            }
+            // The status a panicking program exits with.
+            props.failure_status = 101;
+            let cx = TestCx {
+                config: self.config,
+                props: &props,
+                testpaths: &testpaths,
+                revision: self.revision,
+            };
+            create_dir_all(&cx.output_base_dir()).unwrap();
+            cx.run_doc_example(&example);
+        }
+    }
+
+    fn run_doc_example(&self, example: &examples::Example) {
+        let proc_res = self.compile_test();
+        if example.compile_fail {
+            self.check_no_compiler_crash(&proc_res);
+            if proc_res.status.success() {
+                self.fatal_proc_rec(
+                    "example marked `compile_fail` compiled successfully!",
+                    &proc_res,
+                );
+            }
+            return;
+        }
+        if !proc_res.status.success() {
+            self.fatal_proc_rec("compilation failed!", &proc_res);
+        }
+        if example.no_run {
+            return;
+        }
+
+        let proc_res = self.exec_compiled_test();
+        if example.should_panic {
+            self.check_correct_failure_status(&proc_res);
+        } else if !proc_res.status.success() {
+            self.fatal_proc_rec("test run failed!", &proc_res);
+        }
+    }
+
+    fn run_assembly_test(&self) {
         if self.config.llvm_filecheck.is_none() {
             self.fatal("missing --llvm-filecheck");
         }
 
-        let mut proc_res = self.compile_test_and_save_ir();
+        let (proc_res, output_path) = self.compile_test_and_save_assembly();
         if !proc_res.status.success() {
             self.fatal_proc_rec("compilation failed!", &proc_res);
         }
 
-        proc_res = self.check_ir_with_filecheck();
+        let proc_res = self.verify_with_filecheck(&output_path);
         if !proc_res.status.success() {
             self.fatal_proc_rec("verification with 'FileCheck' failed", &proc_res);
         }
@@ -2062,7 +3628,7 @@ impl<'test> TestCx<'test> {
         } else {
             let root = self.config.find_rust_src_root().unwrap();
             let res = self.cmd2procres(
-                Command::new(&self.config.docck_python)
+                self.command(&self.config.docck_python)
                     .arg(root.join("src/etc/htmldocck.py"))
                     .arg(out_dir)
                     .arg(&self.testpaths.file),
@@ -2368,7 +3934,7 @@ impl<'test> TestCx<'test> {
         }
         fs::create_dir_all(&incremental_dir).unwrap();
 
-        if self.config.verbose {
+        if self.config.is_very_verbose() {
             print!(
                 "init_incremental_test: incremental_dir={}",
                 incremental_dir.display()
@@ -2418,7 +3984,7 @@ impl<'test> TestCx<'test> {
             revision: self.revision,
         };
 
-        if self.config.verbose {
+        if self.config.is_very_verbose() {
             print!(
                 "revision={:?} revision_props={:#?}",
                 revision, revision_props
@@ -2472,7 +4038,7 @@ impl<'test> TestCx<'test> {
             "make"
         };
 
-        let mut cmd = Command::new(make);
+        let mut cmd = self.command(make);
         cmd.current_dir(&self.testpaths.file)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -2503,6 +4069,10 @@ impl<'test> TestCx<'test> {
             cmd.env("NODE", node);
         }
 
+        if let Some(ref dir) = self.config.llvm_bin_dir {
+            cmd.env("LLVM_BIN_DIR", cwd.join(dir));
+        }
+
         if let Some(ref linker) = self.config.linker {
             cmd.env("RUSTC_LINKER", linker);
         }
@@ -2542,9 +4112,8 @@ impl<'test> TestCx<'test> {
             }
         }
 
-        let output = cmd
-            .spawn()
-            .and_then(read2_abbreviated)
+        let output = job::spawn(&mut cmd)
+            .and_then(|(child, _group)| read2_abbreviated(child, self.config.output_limit))
             .expect("failed to spawn `make`");
         if !output.status.success() {
             let res = ProcRes {
@@ -2553,6 +4122,7 @@ impl<'test> TestCx<'test> {
                 stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
                 cmdline: format!("{:?}", cmd),
             };
+            res.note();
             self.fatal_proc_rec("make failed", &res);
         }
     }
@@ -2590,6 +4160,11 @@ impl<'test> TestCx<'test> {
             .iter()
             .any(|s| s.contains("--error-format"));
         let proc_res = self.compile_test();
+        if self.props.should_ice {
+            // The output of a crash isn't stable enough to compare against
+            // `.stderr` files.
+            return self.check_compiler_ice(&proc_res);
+        }
         self.check_if_test_should_compile(&proc_res);
 
         let expected_stderr = self.load_expected_output(UI_STDERR);
@@ -2621,11 +4196,13 @@ impl<'test> TestCx<'test> {
             let unfixed_code = self
                 .load_expected_output_from_path(&self.testpaths.file)
                 .unwrap();
-            let suggestions = get_suggestions_from_json(
-                &proc_res.stderr,
-                &HashSet::new(),
-                Filter::Everything,
-            ).unwrap();
+            let filter = if self.props.rustfix_only_machine_applicable {
+                Filter::MachineApplicableOnly
+            } else {
+                Filter::Everything
+            };
+            let suggestions =
+                get_suggestions_from_json(&proc_res.stderr, &HashSet::new(), filter).unwrap();
             let fixed_code = apply_suggestions(&unfixed_code, &suggestions).expect(&format!(
                 "failed to apply suggestions for {:?} with rustfix",
                 self.testpaths.file
@@ -2663,6 +4240,9 @@ impl<'test> TestCx<'test> {
             if !proc_res.status.success() {
                 self.fatal_proc_rec("test run failed!", &proc_res);
             }
+            if self.props.check_run_results {
+                self.check_run_results(&proc_res);
+            }
         }
         if !explicit && self.config.compare_mode.is_none() {
             if !expected_errors.is_empty() || !proc_res.status.success() {
@@ -2670,7 +4250,7 @@ impl<'test> TestCx<'test> {
                 self.check_expected_errors(expected_errors, &proc_res);
             } else if !self.props.error_patterns.is_empty() || !proc_res.status.success() {
                 // "//~ERROR comments"
-                self.check_error_patterns(&proc_res.stderr, &proc_res);
+                self.check_error_patterns(&[&proc_res.stderr[..]], &proc_res);
             }
         }
 
@@ -2718,8 +4298,9 @@ impl<'test> TestCx<'test> {
             let tests_text_str = String::from(tests_text);
             let mut curr_test: Option<&str> = None;
             let mut curr_test_contents = vec![ExpectedLine::Elision];
+            let mut outdated = vec![];
             for l in tests_text_str.lines() {
-                debug!("line: {:?}", l);
+                trace!("line: {:?}", l);
                 if l.starts_with("// START ") {
                     let (_, t) = l.split_at("// START ".len());
                     curr_test = Some(t);
@@ -2728,7 +4309,13 @@ impl<'test> TestCx<'test> {
                     if Some(t) != curr_test {
                         panic!("mismatched START END test name");
                     }
-                    self.compare_mir_test_output(curr_test.unwrap(), &curr_test_contents);
+                    let test_name = curr_test.unwrap();
+                    if let Err(msg) = self.compare_mir_test_output(test_name, &curr_test_contents) {
+                        if !self.config.bless {
+                            panic!("{}", msg);
+                        }
+                        outdated.push(test_name.to_string());
+                    }
                     curr_test = None;
                     curr_test_contents.clear();
                     curr_test_contents.push(ExpectedLine::Elision);
@@ -2741,7 +4328,71 @@ impl<'test> TestCx<'test> {
                     curr_test_contents.push(ExpectedLine::Text(test_content));
                 }
             }
+            if !outdated.is_empty() {
+                self.bless_mir_dump(&test_file_contents, &outdated);
+            }
+        }
+    }
+
+    /// Replaces the expected MIR of the `outdated` blocks in the test file with
+    /// what was actually dumped. Blessed blocks lose any elisions they had.
+    fn bless_mir_dump(&self, test_file_contents: &str, outdated: &[String]) {
+        let mut blessed = String::with_capacity(test_file_contents.len());
+        let mut in_tests = false;
+        let mut skipping = false;
+        for l in test_file_contents.lines() {
+            if skipping {
+                if !l.starts_with("// END ") {
+                    continue;
+                }
+                skipping = false;
+            }
+            blessed.push_str(l);
+            blessed.push('\n');
+            if l.starts_with("// END RUST SOURCE") {
+                in_tests = true;
+            } else if in_tests && l.starts_with("// START ") {
+                let test_name = l.split_at("// START ".len()).1;
+                if outdated.iter().any(|t| t == test_name) {
+                    let dumped_string = self.read_mir_dump(test_name);
+                    for dumped_line in dumped_string.lines().map(nocomment_mir_line) {
+                        if !dumped_line.is_empty() {
+                            blessed.push_str("// ");
+                            blessed.push_str(dumped_line);
+                            blessed.push('\n');
+                        }
+                    }
+                    skipping = true;
+                }
+            }
+        }
+
+        if let Err(e) = fs::write(&self.testpaths.file, blessed) {
+            self.fatal(&format!(
+                "failed to bless `{}`: {}",
+                self.testpaths.file.display(),
+                e
+            ));
+        }
+        for test_name in outdated {
+            println!(
+                "Blessed expected MIR of {} in {}",
+                test_name,
+                self.testpaths.file.display()
+            );
+        }
+    }
+
+    fn read_mir_dump(&self, test_name: &str) -> String {
+        let output_file = self.get_mir_dump_dir().join(test_name);
+        if !output_file.exists() {
+            panic!(
+                "Output file `{}` from test does not exist",
+                output_file.into_os_string().to_string_lossy()
+            );
         }
+        self.check_mir_test_timestamp(test_name, &output_file);
+        fs::read_to_string(&output_file).unwrap()
     }
 
     fn check_mir_test_timestamp(&self, test_name: &str, output_file: &Path) {
@@ -2762,23 +4413,14 @@ impl<'test> TestCx<'test> {
         }
     }
 
-    fn compare_mir_test_output(&self, test_name: &str, expected_content: &[ExpectedLine<&str>]) {
-        let mut output_file = PathBuf::new();
-        output_file.push(self.get_mir_dump_dir());
-        output_file.push(test_name);
-        debug!("comparing the contests of: {:?}", output_file);
+    fn compare_mir_test_output(
+        &self,
+        test_name: &str,
+        expected_content: &[ExpectedLine<&str>],
+    ) -> Result<(), String> {
+        debug!("comparing the contests of: {:?}", test_name);
         debug!("with: {:?}", expected_content);
-        if !output_file.exists() {
-            panic!(
-                "Output file `{}` from test does not exist",
-                output_file.into_os_string().to_string_lossy()
-            );
-        }
-        self.check_mir_test_timestamp(test_name, &output_file);
-
-        let mut dumped_file = fs::File::open(output_file.clone()).unwrap();
-        let mut dumped_string = String::new();
-        dumped_file.read_to_string(&mut dumped_string).unwrap();
+        let dumped_string = self.read_mir_dump(test_name);
         let mut dumped_lines = dumped_string
             .lines()
             .map(|l| nocomment_mir_line(l))
@@ -2797,8 +4439,8 @@ impl<'test> TestCx<'test> {
         let compare = |expected_line, dumped_line| {
             let e_norm = normalize_mir_line(expected_line);
             let d_norm = normalize_mir_line(dumped_line);
-            debug!("found: {:?}", d_norm);
-            debug!("expected: {:?}", e_norm);
+            trace!("found: {:?}", d_norm);
+            trace!("expected: {:?}", e_norm);
             e_norm == d_norm
         };
 
@@ -2818,14 +4460,14 @@ impl<'test> TestCx<'test> {
                 .map(|l| f(l))
                 .collect::<Vec<_>>()
                 .join("\n");
-            panic!(
+            format!(
                 "Did not find expected line, error: {}\n\
                  Expected Line: {:?}\n\
                  Test Name: {}\n\
                  Expected:\n{}\n\
                  Actual:\n{}",
                 extra_msg, expected_line, test_name, expected_content, normalize_all
-            );
+            )
         };
 
         // We expect each non-empty line to appear consecutively, non-consecutive lines
@@ -2841,7 +4483,7 @@ impl<'test> TestCx<'test> {
 
                     if !compare(expected_line, dumped_line) {
                         error!("{:?}", start_block_line);
-                        error(
+                        return Err(error(
                             expected_line,
                             format!(
                                 "Mismatch in lines\n\
@@ -2850,7 +4492,7 @@ impl<'test> TestCx<'test> {
                                 start_block_line.unwrap_or("None"),
                                 dumped_line
                             ),
-                        );
+                        ));
                     }
                 }
                 Some(&ExpectedLine::Elision) => {
@@ -2870,13 +4512,17 @@ impl<'test> TestCx<'test> {
                             }
                         }
                         if !found {
-                            error(expected_line, "ran out of mir dump to match against".into());
+                            return Err(error(
+                                expected_line,
+                                "ran out of mir dump to match against".into(),
+                            ));
                         }
                     }
                 }
                 None => {}
             }
         }
+        Ok(())
     }
 
     fn get_mir_dump_dir(&self) -> PathBuf {
@@ -2930,30 +4576,44 @@ impl<'test> TestCx<'test> {
         normalized
     }
 
+    /// The file with the expected output. The files for the compare mode, if
+    /// any, come first, and of the files for a mode, the ones for this target or
+    /// stage in particular.
     fn expected_output_path(&self, kind: &str) -> PathBuf {
-        let mut path = expected_output_path(
-            &self.testpaths,
-            self.revision,
-            &self.config.compare_mode,
-            kind,
-        );
-
-        if !path.exists() {
-            if let Some(CompareMode::Polonius) = self.config.compare_mode {
-                path = expected_output_path(
-                    &self.testpaths,
-                    self.revision,
-                    &Some(CompareMode::Nll),
-                    kind,
-                );
-            }
+        let mut modes = vec![self.config.compare_mode.clone()];
+        if let Some(CompareMode::Polonius) = self.config.compare_mode {
+            modes.push(Some(CompareMode::Nll));
         }
-
-        if !path.exists() {
-            path = expected_output_path(&self.testpaths, self.revision, &None, kind);
+        if self.config.compare_mode.is_some() {
+            modes.push(None);
+        }
+        for mode in &modes {
+            if let Some(path) = self.target_expected_output_path(kind, mode) {
+                return path;
+            }
+            let path = expected_output_path(&self.testpaths, self.revision, mode, None, kind);
+            if path.exists() {
+                return path;
+            }
         }
+        expected_output_path(&self.testpaths, self.revision, &None, None, kind)
+    }
 
-        path
+    /// The file with the output expected in compare mode `mode` on this target
+    /// or with this stage of the compiler in particular, named after the
+    /// target's triple or its OS or after the stage, if there is one.
+    fn target_expected_output_path(
+        &self,
+        kind: &str,
+        mode: &Option<CompareMode>,
+    ) -> Option<PathBuf> {
+        self.config
+            .output_targets()
+            .into_iter()
+            .map(|target| {
+                expected_output_path(&self.testpaths, self.revision, mode, Some(target), kind)
+            })
+            .find(|path| path.exists())
     }
 
     fn load_expected_output(&self, kind: &str) -> String {
@@ -2996,6 +4656,7 @@ impl<'test> TestCx<'test> {
         }
 
         if !self.config.bless {
+            report::note_diff(kind, expected, actual);
             if expected.is_empty() {
                 println!("normalized {}:\n{}\n", kind, actual);
             } else {
@@ -3032,12 +4693,18 @@ impl<'test> TestCx<'test> {
 
         let mut files = vec![output_file];
         if self.config.bless {
-            files.push(expected_output_path(
-                self.testpaths,
-                self.revision,
-                &self.config.compare_mode,
-                kind,
-            ));
+            // Output expected on this target only stays where it is, but
+            // output of a compare mode never goes to the files without one.
+            let compare_mode = &self.config.compare_mode;
+            files.push(self.target_expected_output_path(kind, compare_mode).unwrap_or_else(|| {
+                expected_output_path(
+                    self.testpaths,
+                    self.revision,
+                    &self.config.compare_mode,
+                    None,
+                    kind,
+                )
+            }));
         }
 
         for output_file in &files {
@@ -3067,11 +4734,18 @@ impl<'test> TestCx<'test> {
         }
     }
 
-    fn prune_duplicate_output(&self, mode: CompareMode, kind: &str, canon_content: &str) {
+    fn prune_duplicate_output(
+        &self,
+        mode: CompareMode,
+        target: Option<&str>,
+        kind: &str,
+        canon_content: &str,
+    ) {
         let examined_path = expected_output_path(
             &self.testpaths,
             self.revision,
             &Some(mode),
+            target,
             kind,
         );
 
@@ -3086,17 +4760,23 @@ impl<'test> TestCx<'test> {
 
     fn prune_duplicate_outputs(&self, modes: &[CompareMode]) {
         if self.config.bless {
+            let mut targets = vec![None];
+            targets.extend(self.config.output_targets().into_iter().map(Some));
             for kind in UI_EXTENSIONS {
-                let canon_comparison_path = expected_output_path(
-                    &self.testpaths,
-                    self.revision,
-                    &None,
-                    kind,
-                );
+                for &target in &targets {
+                    let canon_comparison_path = expected_output_path(
+                        &self.testpaths,
+                        self.revision,
+                        &None,
+                        target,
+                        kind,
+                    );
 
-                if let Ok(canon) = self.load_expected_output_from_path(&canon_comparison_path) {
-                    for mode in modes {
-                        self.prune_duplicate_output(mode.clone(), kind, &canon);
+                    if let Ok(canon) = self.load_expected_output_from_path(&canon_comparison_path)
+                    {
+                        for mode in modes {
+                            self.prune_duplicate_output(mode.clone(), target, kind, &canon);
+                        }
                     }
                 }
             }
@@ -3105,7 +4785,7 @@ impl<'test> TestCx<'test> {
 
     fn create_stamp(&self) {
         let mut f = File::create(::stamp(&self.config, self.testpaths, self.revision)).unwrap();
-        f.write_all(compute_stamp_hash(&self.config).as_bytes())
+        f.write_all(compute_stamp_hash(&self.config, self.testpaths).as_bytes())
             .unwrap();
     }
 }
@@ -3123,11 +4803,40 @@ pub struct ProcRes {
 }
 
 impl ProcRes {
+    /// How the process ended, e.g. "exited with exit code: 1" or "was
+    /// terminated by SIGSEGV".
+    fn describe_status(&self) -> String {
+        match (self.status.code(), termination_signal(&self.status)) {
+            (None, Some(signal)) => format!("was terminated by {}", signal),
+            (None, None) => "was terminated by a signal".to_string(),
+            (Some(_), _) => format!("exited with {}", self.status),
+        }
+    }
+
+    /// The status, naming the signal if one terminated the process.
+    fn status_with_signal(&self) -> String {
+        match termination_signal(&self.status) {
+            Some(signal) => format!("{} ({})", self.status, signal),
+            None => self.status.to_string(),
+        }
+    }
+
+    /// Remember this process for the failure bundle of the current test.
+    fn note(&self) {
+        report::note_process(
+            &self.cmdline,
+            &self.status_with_signal(),
+            &self.stdout,
+            &self.stderr,
+        );
+    }
+
     pub fn fatal(&self, err: Option<&str>) -> ! {
         if let Some(e) = err {
             println!("\nerror: {}", e);
+            report::note_failure(&format!("error: {}", e));
         }
-        print!(
+        let details = format!(
             "\
              status: {}\n\
              command: {}\n\
@@ -3140,8 +4849,13 @@ impl ProcRes {
              {}\n\
              ------------------------------------------\n\
              \n",
-            self.status, self.cmdline, self.stdout, self.stderr
+            self.status_with_signal(),
+            self.cmdline,
+            self.stdout,
+            self.stderr
         );
+        print!("{}", details);
+        report::note_failure(&details);
         panic!();
     }
 }
@@ -3183,12 +4897,30 @@ fn nocomment_mir_line(line: &str) -> &str {
     }
 }
 
-fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
+/// Reads the output of `child` until it exits, keeping at most `limit` bytes
+/// of each of its stdout and stderr: some from the start, the rest from the
+/// end, with a note of how much was left out in between.
+fn read2_abbreviated(child: Child, limit: usize) -> io::Result<Output> {
+    read2_abbreviated_with_max_rss(child, limit, None, None).map(|(output, _)| output)
+}
+
+/// Like `read2_abbreviated`, but also returns the peak memory use of the
+/// child in bytes, on the platforms that can tell. If `stream_prefix` is
+/// given, the child's output is also printed line by line as it arrives. If
+/// `spill` is given, the complete stdout and stderr are written to those
+/// files as they arrive.
+fn read2_abbreviated_with_max_rss(
+    mut child: Child,
+    limit: usize,
+    stream_prefix: Option<String>,
+    mut spill: Option<(File, File)>,
+) -> io::Result<(Output, Option<u64>)> {
     use read2::read2;
     use std::mem::replace;
 
-    const HEAD_LEN: usize = 160 * 1024;
-    const TAIL_LEN: usize = 256 * 1024;
+    // Split like the default limit, 160 KiB of head and 256 KiB of tail.
+    let head_len = limit / 13 * 5;
+    let tail_len = limit - head_len;
 
     enum ProcOutput {
         Full(Vec<u8>),
@@ -3200,17 +4932,17 @@ fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
     }
 
     impl ProcOutput {
-        fn extend(&mut self, data: &[u8]) {
+        fn extend(&mut self, data: &[u8], head_len: usize, tail_len: usize) {
             let new_self = match *self {
                 ProcOutput::Full(ref mut bytes) => {
                     bytes.extend_from_slice(data);
                     let new_len = bytes.len();
-                    if new_len <= HEAD_LEN + TAIL_LEN {
+                    if new_len <= head_len + tail_len {
                         return;
                     }
-                    let tail = bytes.split_off(new_len - TAIL_LEN).into_boxed_slice();
+                    let tail = bytes.split_off(new_len - tail_len).into_boxed_slice();
                     let head = replace(bytes, Vec::new());
-                    let skipped = new_len - HEAD_LEN - TAIL_LEN;
+                    let skipped = new_len - head_len - tail_len;
                     ProcOutput::Abbreviated {
                         head,
                         skipped,
@@ -3223,11 +4955,11 @@ fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
                     ..
                 } => {
                     *skipped += data.len();
-                    if data.len() <= TAIL_LEN {
+                    if data.len() <= tail_len {
                         tail[..data.len()].copy_from_slice(data);
                         tail.rotate_left(data.len());
                     } else {
-                        tail.copy_from_slice(&data[(data.len() - TAIL_LEN)..]);
+                        tail.copy_from_slice(&data[(data.len() - tail_len)..]);
                     }
                     return;
                 }
@@ -3253,21 +4985,152 @@ fn read2_abbreviated(mut child: Child) -> io::Result<Output> {
 
     let mut stdout = ProcOutput::Full(Vec::new());
     let mut stderr = ProcOutput::Full(Vec::new());
+    let mut streamer = stream_prefix.map(LineStreamer::new);
 
     drop(child.stdin.take());
     read2(
         child.stdout.take().unwrap(),
         child.stderr.take().unwrap(),
-        &mut |is_stdout, data, _| {
-            if is_stdout { &mut stdout } else { &mut stderr }.extend(data);
+        &mut |is_stdout, data, eof| {
+            if let Some(ref mut streamer) = streamer {
+                streamer.feed(is_stdout, data, eof);
+            }
+            if let Some((ref mut out, ref mut err)) = spill {
+                // Keep reading even if the disk is full, so the child
+                // doesn't block on a full pipe.
+                let _ = if is_stdout { out } else { err }.write_all(data);
+            }
+            let output = if is_stdout { &mut stdout } else { &mut stderr };
+            output.extend(data, head_len, tail_len);
             data.clear();
         },
     )?;
-    let status = child.wait()?;
+    let (status, max_rss) = wait_with_max_rss(&mut child)?;
 
-    Ok(Output {
+    let output = Output {
         status,
         stdout: stdout.into_bytes(),
         stderr: stderr.into_bytes(),
-    })
+    };
+    Ok((output, max_rss))
+}
+
+/// Prints the output of a process line by line while it runs, each line
+/// prefixed with the test the process belongs to.
+struct LineStreamer {
+    prefix: String,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl LineStreamer {
+    fn new(prefix: String) -> LineStreamer {
+        LineStreamer {
+            prefix,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, is_stdout: bool, data: &[u8], eof: bool) {
+        let (pending, stream) = if is_stdout {
+            (&mut self.stdout, "stdout")
+        } else {
+            (&mut self.stderr, "stderr")
+        };
+        pending.extend_from_slice(data);
+
+        let complete = if eof {
+            pending.len()
+        } else {
+            match pending.iter().rposition(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => return,
+            }
+        };
+        let lines = pending.drain(..complete).collect::<Vec<_>>();
+
+        // Written straight to stdout, as libtest only shows what tests print
+        // once they're done.
+        let out = io::stdout();
+        let mut out = out.lock();
+        for line in String::from_utf8_lossy(&lines).lines() {
+            let _ = writeln!(out, "[{} {}] {}", self.prefix, stream, line);
+        }
+        let _ = out.flush();
+    }
+}
+
+/// Waits for `child` to exit, asking the kernel for its peak resident set
+/// size along the way.
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+fn wait_with_max_rss(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    use std::mem;
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        let ret = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+        if ret != -1 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    // Linux and FreeBSD report kilobytes, macOS bytes.
+    let max_rss = usage.ru_maxrss as u64;
+    let max_rss = if cfg!(target_os = "macos") { max_rss } else { max_rss * 1024 };
+    Ok((ExitStatus::from_raw(status), Some(max_rss)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+fn wait_with_max_rss(child: &mut Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    child.wait().map(|status| (status, None))
+}
+
+#[test]
+fn test_nm_symbols() {
+    let output = "\
+liba.rlib:
+
+a.o:
+0000000000000000 T _ZN1a4used17h0123456789abcdefE
+                 U _ZN3std2io5stdio6_print17h0123456789abcdefE
+0000000000000010 t local
+                 w __gmon_start__
+";
+    assert_eq!(nm_symbols(output), vec!["_ZN1a4used17h0123456789abcdefE", "local"]);
+}
+
+#[test]
+fn test_linker_map_symbols() {
+    let map = "\
+ main
+
+ Timestamp is 5b0f8e32 (Thu May 31 08:00:02 2018)
+
+ Preferred load address is 0000000140000000
+
+ Start         Length     Name                   Class
+ 0001:00000000 0001a2c0H .text$mn                CODE
+
+  Address         Publics by Value              Rva+Base               Lib:Object
+
+ 0000:00000000       ___safe_se_handler_count   0000000000000000     <absolute>
+ 0001:00000010       _ZN4main4used17h0123456789abcdefE 0000000140001010 f   main.o
+
+ entry point at        0001:00001234
+
+ Static symbols
+
+ 0001:00000000       local                      0000000140001000 f   main.o
+";
+    assert_eq!(
+        linker_map_symbols(map),
+        vec!["___safe_se_handler_count", "_ZN4main4used17h0123456789abcdefE", "local"]
+    );
 }
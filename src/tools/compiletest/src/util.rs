@@ -10,8 +10,13 @@
 
 use std::ffi::OsStr;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use common::Config;
+use env_logger::filter::{self, Filter};
+use log::{self, LevelFilter, Log, Metadata, Record};
+use test::ColorConfig;
+#[cfg(unix)]
+use libc;
 
 /// Conversion table from triple OS name to Rust SYSNAME
 const OS_TABLE: &'static [(&'static str, &'static str)] = &[
@@ -73,18 +78,25 @@ const ARCH_TABLE: &'static [(&'static str, &'static str)] = &[
 
 pub fn matches_os(triple: &str, name: &str) -> bool {
     // For the wasm32 bare target we ignore anything also ignored on emscripten
-    // and then we also recognize `wasm32-bare` as the os for the target
+    if triple == "wasm32-unknown-unknown" && name == "emscripten" {
+        return true;
+    }
+    get_os(triple) == Some(name)
+}
+
+/// The OS of the target `triple`, if it is one we know.
+pub fn get_os(triple: &str) -> Option<&'static str> {
+    // We recognize `wasm32-bare` as the os for the wasm32 bare target
     if triple == "wasm32-unknown-unknown" {
-        return name == "emscripten" || name == "wasm32-bare";
+        return Some("wasm32-bare");
     }
     let triple: Vec<_> = triple.split('-').collect();
-    for &(triple_os, os) in OS_TABLE {
-        if triple.contains(&triple_os) {
-            return os == name;
-        }
-    }
-    panic!("Cannot determine OS from triple");
+    OS_TABLE
+        .iter()
+        .find(|&&(triple_os, _)| triple.contains(&triple_os))
+        .map(|&(_, os)| os)
 }
+
 pub fn get_arch(triple: &str) -> &'static str {
     let triple: Vec<_> = triple.split('-').collect();
     for &(triple_arch, arch) in ARCH_TABLE {
@@ -124,11 +136,143 @@ fn path_div() -> &'static str {
     ";"
 }
 
-pub fn logv(config: &Config, s: String) {
-    debug!("{}", s);
-    if config.verbose {
-        println!("{}", s);
+/// Where `program` is, if it exists: either the path itself if it has a
+/// directory in it, or the first match in `PATH`.
+pub fn find_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf()).filter(|p| p.is_file());
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| {
+            let exe = dir.join(program).with_extension(env::consts::EXE_EXTENSION);
+            vec![dir.join(program), exe]
+        })
+        .find(|p| p.is_file())
+}
+
+/// Quotes `arg` for a POSIX shell, unless it doesn't need to be.
+#[cfg(not(windows))]
+pub fn quote_arg(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Quotes `arg` the way programs on Windows split their command line, unless
+/// it doesn't need to be.
+#[cfg(windows)]
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c| c == ' ' || c == '\t' || c == '"') {
+        return arg.to_string();
+    }
+    // Backslashes are only special right before a quote.
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend((0..backslashes * 2 + 1).map(|_| '\\'));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.extend((0..backslashes).map(|_| '\\'));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
     }
+    quoted.extend((0..backslashes * 2).map(|_| '\\'));
+    quoted.push('"');
+    quoted
+}
+
+/// Prints log messages with `println!` rather than to stderr, so that libtest
+/// captures them along with the rest of the output of the test they're about.
+struct Logger {
+    filter: Filter,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.matches(record) {
+            println!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging as asked for with `--log` or `COMPILETEST_LOG`, using
+/// `env_logger`'s filter syntax. Without either, `--verbose` logs at `info`
+/// and `-vv` at `debug` level.
+pub fn init_logging(config: &Config) {
+    let mut builder = filter::Builder::new();
+    match config.log {
+        Some(ref spec) => {
+            builder.parse(spec);
+        }
+        None => {
+            builder.filter_level(match config.verbosity {
+                0 => LevelFilter::Warn,
+                1 => LevelFilter::Info,
+                _ => LevelFilter::Debug,
+            });
+        }
+    }
+    let filter = builder.build();
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(Logger { filter })).expect("logger already set");
+}
+
+/// Whether output meant for humans should be colored, as asked for with
+/// `--color`. `auto` colors only when stdout is a terminal.
+pub fn use_color(config: &Config) -> bool {
+    match config.color {
+        ColorConfig::AlwaysColor => true,
+        ColorConfig::NeverColor => false,
+        ColorConfig::AutoColor => stdout_is_tty(),
+    }
+}
+
+/// Terminal colors used by `paint`.
+#[derive(Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+}
+
+/// Wraps `text` in the ANSI codes for `color`, unless colors are disabled.
+pub fn paint(config: &Config, color: Color, text: &str) -> String {
+    if !use_color(config) {
+        return text.to_string();
+    }
+    let code = match color {
+        Color::Red => "31",
+        Color::Green => "32",
+        Color::Yellow => "33",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+#[cfg(unix)]
+fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) != 0 }
+}
+
+#[cfg(not(unix))]
+fn stdout_is_tty() -> bool {
+    false
 }
 
 pub trait PathBufExt {
@@ -150,3 +294,22 @@ impl PathBufExt for PathBuf {
         }
     }
 }
+
+#[test]
+#[cfg(not(windows))]
+fn test_quote_arg() {
+    assert_eq!(quote_arg("-Copt-level=2"), "-Copt-level=2");
+    assert_eq!(quote_arg("a b"), "'a b'");
+    assert_eq!(quote_arg("it's"), "'it'\\''s'");
+    assert_eq!(quote_arg("$HOME"), "'$HOME'");
+    assert_eq!(quote_arg(""), "''");
+}
+
+#[test]
+#[cfg(windows)]
+fn test_quote_arg() {
+    assert_eq!(quote_arg(r"C:\a\b.rs"), r"C:\a\b.rs");
+    assert_eq!(quote_arg(r"C:\a b\"), r#""C:\a b\\""#);
+    assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+    assert_eq!(quote_arg(""), r#""""#);
+}
@@ -25,6 +25,7 @@ pub fn check(path: &Path, bad: &mut bool) {
                     // $testname.$mode.stderr
                     // $testname.$revision.stderr
                     // $testname.$revision.$mode.stderr
+                    // $testname.$target.stderr (and the above with $target)
                     //
                     // For now, just make sure that there is a corresponding
                     // $testname.rs file.